@@ -0,0 +1,28 @@
+extern crate moomoot;
+
+use moomoot::MooMoot;
+use moomoot::patch::Watcher;
+use std::{env, time, thread};
+
+// edit the patch file while this runs : changes are heard on save.
+// cargo run --example livecode -- my.patch
+fn main() {
+    let path = env::args().nth(1).unwrap_or("live.patch".to_string());
+
+    let mut m = MooMoot::start();
+    let mut watcher = Watcher::new(&path);
+
+    loop {
+        match watcher.poll() {
+            Some(Ok(patch)) => {
+                match m.reload_patch(&patch) {
+                    Ok(()) => println!("{} reloaded", path),
+                    Err(err) => println!("{}: {}", path, err),
+                }
+            }
+            Some(Err(err)) => println!("{}: {}", path, err),
+            None => (),
+        }
+        thread::sleep(time::Duration::from_millis(200));
+    }
+}
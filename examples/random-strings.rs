@@ -15,8 +15,8 @@ fn main() {
     let mut m = MooMoot::start();
 
     let root = m.root_mixer();
-    let blah = m.add_mixer(&root, "blah");
-    let noise = m.add_mixer(&root, "noiz");
+    let blah = m.add_mixer(&root, "blah").unwrap();
+    let noise = m.add_mixer(&root, "noiz").unwrap();
 
     // white noise beeing parameter less ..
     m.add_synth(&noise, WhiteNoise::new());
@@ -28,8 +28,8 @@ fn main() {
     let mut note: f64 = 1.0;
     let mut random: u64 = 852;
 
-    let sines = m.add_mixer(&blah, "sines");
-    let strings = m.add_mixer(&blah, "strings");
+    let sines = m.add_mixer(&blah, "sines").unwrap();
+    let strings = m.add_mixer(&blah, "strings").unwrap();
 
     for i in 1..10 {
         let freq_s = format!("freq * {}", f64::from(i * i)*1.1);
@@ -53,7 +53,7 @@ fn main() {
         thread::sleep(time::Duration::from_millis(250));
 
         m.set_bus_value("freq", note * 50.0);
-        m.set_bus_value("noize_pan", 0.5 + note.cos() * 0.5);
+        m.ramp_bus("noize_pan", 0.5 + note.cos() * 0.5, 0.2);
 
         thread::sleep(time::Duration::from_millis(250 + random));
 
@@ -1,19 +1,62 @@
 use jack::prelude as j;
 use std::sync::mpsc::*;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use uuid::Uuid;
-use tree::mmtree::MMTree;
+use tree::mmtree::{Capacities, MMTree};
+use tree::mixer::Mixer;
 use traits::*;
 use synth::Synth;
 use efx::Efx;
+use params::ParamValue;
+use utils::scheduler::Scheduler;
+use tree::sequencer::{MakerH, Sequencer, Steps, VoiceMaker};
+use sequencer::{Instrument, Pattern};
+use transport::{Transport, TransportBuses};
+use registry::Registry;
+use patch::{Patch, PatchError};
+use loader::Loaded;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use snapshot::TreeSnapshot;
+use tree::meter::{self, Meter, MeterProbe};
+use tree::voices::VoiceLimit;
+use tree::arena::{Key, Keys, ROOT};
+use tree::garbage::{self, Freed};
+use tree::pbus::{Bus, BusSystem, Reader};
+use stats::{self, DspStats, PeriodStats, StatsRecorder};
 
 
 // #[derive(Debug)]
-enum InternalCmd {
+pub enum InternalCmd {
     // internal commands to pass to RT thread
-    AddMixer(String, String), // parent , kid
-    AddEfx(String, Box<Efx>),
-    AddSynth(String, Box<Synth>),
-    SetBusValue(String, f64),
+    AddMixer(Key, Key, Mixer), // parent , kid
+    AddEfx(Key, Box<dyn Efx>),
+    AddSynth(Key, Box<dyn Synth>, i32), // mixer, priority
+    AddNamedSynth(Key, String, Box<dyn Synth>), // mixer, node id
+    AddNamedEfx(Key, String, Box<dyn Efx>),
+    RemoveNode(Key, String),
+    SetNodeParameter(Key, String, usize, ParamValue), // mixer, node, parameter index
+    RemoveMixer(Key),
+    MoveMixer(Key, Key), // mixer, new parent
+    AddReturn(Key, Mixer),
+    SetVoiceLimit(Key, Option<VoiceLimit>),
+    SetRelease(usize), // frames
+    AddSend(Key, Key, ParamValue), // from, return
+    RemoveSend(Key, Key),
+    SetBusValue(Bus<f64>, f64),
+    RampBus(Bus<f64>, f64, usize), // target, nb frames
+    AddTrack(String, Key, Steps), // track, mixer
+    SetPattern(String, Steps),
+    RemoveTrack(String),
+    SetTempo(f64),
+    SetSwing(f64),
+    FollowTransport(bool),
+    At(u64, Box<InternalCmd>),   // frame of the audio clock
+    Snapshot(Sender<TreeSnapshot>),
+    AddMeter(Option<Key>, MeterProbe), // mixer, or the main output
+    ProfileMixers(bool),
+    MixerTimes(Sender<(usize, Vec<(String, Duration)>)>), // frames, cpu time
 }
 
 
@@ -23,33 +66,143 @@ struct InternalProcess {
     ports: (j::Port<j::AudioOutSpec>, j::Port<j::AudioOutSpec>), // (R,L) jack ports
     rx: Receiver<InternalCmd>,
     synth_tree: MMTree,
+    scheduler: Scheduler<InternalCmd>,
+    sequencer: Sequencer,
+    transport_buses: TransportBuses,
+    frame: u64,
+    clock: Arc<AtomicUsize>, // frame, for the non RT side
+    stats: StatsRecorder,
+    sample_rate: f64,
+    freed: SyncSender<Freed>, // removed nodes, dropped on the control side
+    failed: Arc<AtomicUsize>, // commands, for the non RT side
+}
+
+// JACK notifications ( other thread than the process callback )
+struct Notifications {
+    xruns: Arc<AtomicUsize>,
+}
+
+impl j::NotificationHandler for Notifications {
+    fn xrun(&mut self, _: &j::Client) -> j::JackControl {
+        self.xruns.fetch_add(1, Ordering::Relaxed);
+        j::JackControl::Continue
+    }
 }
 
 impl InternalProcess {
     // need lifetimes here so that we know that the borrow is released
-    fn new(client: &j::Client) -> (Sender<InternalCmd>, InternalProcess) {
+    fn new(
+        client: &j::Client,
+        capacities: &Capacities,
+        transport_buses: TransportBuses,
+        clock: Arc<AtomicUsize>,
+        stats: StatsRecorder,
+        freed: SyncSender<Freed>,
+        failed: Arc<AtomicUsize>,
+    ) -> (Sender<InternalCmd>, InternalProcess) {
         let port_right = client
-            .register_port("moomoot_r", j::AudioOutSpec::default())
+            .register_port("moomoot_r", j::AudioOutSpec)
             .unwrap();
         let port_left = client
-            .register_port("moomoot_l", j::AudioOutSpec::default())
+            .register_port("moomoot_l", j::AudioOutSpec)
             .unwrap();
 
         let (sx, rx) = channel();
         let m = InternalProcess {
             ports: (port_right, port_left),
-            rx: rx,
-            synth_tree: MMTree::new(),
+            rx,
+            synth_tree: MMTree::with_capacities(capacities),
+            scheduler: Scheduler::new(),
+            sequencer: Sequencer::new(1. / client.sample_rate() as f64),
+            transport_buses,
+            frame: 0,
+            clock,
+            stats,
+            sample_rate: client.sample_rate() as f64,
+            freed,
+            failed,
         };
         (sx, m)
     }
 
     fn command(&mut self, cmd: InternalCmd) -> Result<(), &str> {
+        InternalProcess::run_command(
+            &mut self.synth_tree,
+            &mut self.scheduler,
+            &mut self.sequencer,
+            cmd,
+        )
+    }
+
+    // (without borrowing the ports)
+    fn run_command<'a>(
+        synth_tree: &'a mut MMTree,
+        scheduler: &mut Scheduler<InternalCmd>,
+        sequencer: &'a mut Sequencer,
+        cmd: InternalCmd,
+    ) -> Result<(), &'a str> {
         match cmd {
-            InternalCmd::AddSynth(p, synth) => self.synth_tree.add_synth(&p, synth),
-            InternalCmd::AddEfx(p, efx) => self.synth_tree.add_efx(&p, efx),
-            InternalCmd::AddMixer(p, mixer_id) => self.synth_tree.add_mixer(&p, &mixer_id),
-            InternalCmd::SetBusValue(bus, value) => self.synth_tree.set_bus_value(&bus, value),
+            InternalCmd::AddSynth(p, synth, priority) => {
+                synth_tree.add_synth_with_priority(p, synth, priority)
+            }
+            InternalCmd::AddEfx(p, efx) => synth_tree.add_efx(p, efx),
+            InternalCmd::AddNamedSynth(p, id, synth) => synth_tree.add_named_synth(p, id, synth),
+            InternalCmd::AddNamedEfx(p, id, efx) => synth_tree.add_named_efx(p, id, efx),
+            InternalCmd::RemoveNode(p, id) => synth_tree.remove_node(p, &id),
+            InternalCmd::SetNodeParameter(p, id, idx, value) => {
+                synth_tree.set_node_parameter(p, id, idx, value)
+            }
+            InternalCmd::RemoveMixer(mixer) => synth_tree.remove_mixer(mixer),
+            InternalCmd::AddMixer(p, key, mixer) => synth_tree.insert_mixer(p, key, mixer),
+            InternalCmd::MoveMixer(mixer, p) => synth_tree.move_mixer(mixer, p),
+            InternalCmd::AddReturn(key, mixer) => synth_tree.insert_return(key, mixer),
+            InternalCmd::SetVoiceLimit(mixer, limit) => synth_tree.set_voice_limit(mixer, limit),
+            InternalCmd::SetRelease(frames) => {
+                synth_tree.set_release(frames);
+                Ok(())
+            }
+            InternalCmd::AddSend(from, to, amount) => synth_tree.add_send(from, to, amount),
+            InternalCmd::RemoveSend(from, to) => synth_tree.remove_send(from, to),
+            InternalCmd::SetBusValue(bus, value) => {
+                synth_tree.set_bus(&bus, value);
+                Ok(())
+            }
+            InternalCmd::RampBus(bus, value, frames) => synth_tree.ramp_bus(bus, value, frames),
+            InternalCmd::AddTrack(id, mixer, steps) => {
+                sequencer.add_track(synth_tree, &id, mixer, steps)
+            }
+            InternalCmd::SetPattern(id, steps) => sequencer.set_pattern(synth_tree, &id, steps),
+            InternalCmd::RemoveTrack(id) => sequencer.remove_track(synth_tree, &id),
+            InternalCmd::SetTempo(bpm) => {
+                sequencer.set_tempo(bpm);
+                Ok(())
+            }
+            InternalCmd::SetSwing(swing) => {
+                sequencer.set_swing(swing);
+                Ok(())
+            }
+            InternalCmd::At(frame, cmd) => {
+                scheduler.push(frame, *cmd).map_err(|_| "too many timed commands")
+            }
+            InternalCmd::FollowTransport(follow) => {
+                sequencer.follow_transport(follow);
+                Ok(())
+            }
+            InternalCmd::AddMeter(Some(mixer), probe) => synth_tree.add_meter(mixer, probe),
+            InternalCmd::AddMeter(None, probe) => synth_tree.add_output_meter(probe),
+            InternalCmd::Snapshot(reply) => {
+                // (nobody waiting anymore is fine)
+                reply.send(synth_tree.snapshot()).ok();
+                Ok(())
+            }
+            InternalCmd::ProfileMixers(profiling) => {
+                synth_tree.set_profiling(profiling);
+                Ok(())
+            }
+            InternalCmd::MixerTimes(reply) => {
+                reply.send(synth_tree.take_mixer_times()).ok();
+                Ok(())
+            }
         }
     }
 }
@@ -57,12 +210,35 @@ impl InternalProcess {
 impl j::ProcessHandler for InternalProcess {
     // realtime callback
     fn process(&mut self, _: &j::Client, ps: &j::ProcessScope) -> j::JackControl {
+        let start = Instant::now();
 
-        // treat command buffer
-        while let Ok(msg) = self.rx.try_recv() {
-            self.command(msg).expect("panic in synth tree:");
+        // treat command buffer. a command can fail on a handle to a
+        // removed mixer, or a cycle : the tree is left as it is rather than
+        // stopping the RT thread ( see `MooMoot::failed_commands` ). with the
+        // garbage full, the commands wait for the next period.
+        while self.synth_tree.has_room() {
+            match self.rx.try_recv() {
+                Ok(msg) => {
+                    if self.command(msg).is_err() {
+                        self.failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                Err(_) => break,
+            }
         }
 
+        let transport = Transport::query(ps);
+        transport.publish(&self.transport_buses, &mut self.synth_tree);
+        self.sequencer.sync(
+            transport.rolling,
+            transport.beats(),
+            transport.bpm(),
+        );
+        // following a stopped transport : the audio clock stands still, timed
+        // commands and bus ramps wait for it
+        let held = !self.sequencer.is_running();
+        self.synth_tree.hold_clock(held);
+
         // Get output buffer
         let mut out_right = j::AudioOutPort::new(&mut self.ports.0, ps);
         let mut out_left = j::AudioOutPort::new(&mut self.ports.1, ps);
@@ -72,6 +248,28 @@ impl j::ProcessHandler for InternalProcess {
 
         // Write output
         for (v_r, v_l) in out_r.iter_mut().zip(out_l.iter_mut()) {
+            if !held {
+                // timed commands, at their exact frame ( or once the
+                // garbage has room )
+                while self.synth_tree.has_room() {
+                    let cmd = match self.scheduler.pop(self.frame) {
+                        Some(cmd) => cmd,
+                        None => break,
+                    };
+                    let res = InternalProcess::run_command(
+                        &mut self.synth_tree,
+                        &mut self.scheduler,
+                        &mut self.sequencer,
+                        cmd,
+                    );
+                    if res.is_err() {
+                        self.failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                self.frame += 1;
+            }
+            self.sequencer.tick(&mut self.synth_tree);
+
             match self.synth_tree.sample() {
                 SoundSample::Sample(sample) => {
                     match sample {
@@ -89,13 +287,27 @@ impl j::ProcessHandler for InternalProcess {
             }
         }
 
+        self.clock.store(self.frame as usize, Ordering::Relaxed);
+        self.synth_tree.send_freed(&self.freed);
+        self.stats.period(
+            start.elapsed(),
+            ps.n_frames() as f64 / self.sample_rate,
+            self.synth_tree.synth_count(),
+            self.synth_tree.mixer_count(),
+        );
+
         // Continue as normal
         j::JackControl::Continue
     }
 }
 
-/// Opaque type for a mixer
-pub struct MixerH(String);
+/// Opaque type for a mixer ( a generation checked index : it doesn't find
+/// another mixer once its own is removed, what's done with it then fails,
+/// see `MooMoot::failed_commands` )
+pub struct MixerH(Key);
+
+/// Opaque type for a sequencer track
+pub struct TrackH(String);
 
 
 /// The MooMooT Synthetizer object.
@@ -109,28 +321,86 @@ pub struct MixerH(String);
 /// moomoot.add_synth(&root_mixer, Sine::new(SineParams::default().frequency(440.0)));
 /// ```
 pub struct MooMoot {
-    async_client: j::AsyncClient<(), InternalProcess>,
+    async_client: j::AsyncClient<Notifications, InternalProcess>,
     sample_rate: f64,
     send_channel: Sender<InternalCmd>,
+    clock: Arc<AtomicUsize>,
+    stats: Reader<PeriodStats>,
+    xruns: Arc<AtomicUsize>,
+    failed: Arc<AtomicUsize>,
+    registry: Registry,
+    loaded: Loaded, // from patches
+    capacities: Capacities,
+    // the buses by name : what goes to the RT thread is connected here first
+    buses: Arc<Mutex<BusSystem>>,
+    keys: Keys,
+    freed: Receiver<usize>, // mixer slots, back to `Keys`
+    tracks: HashMap<String, MakerH>,
 }
 
 impl MooMoot {
     /// Create a MooMooT instance, instantiate the jack port
     /// and starts jack RT thread.
     pub fn start() -> MooMoot {
+        MooMoot::start_with(Capacities::default())
+    }
+
+    /// same as `start`, with room for more ( or fewer ) nodes. the room is
+    /// reserved up front : the RT thread doesn't allocate.
+    /// # Example
+    /// ```no_run
+    ///  // requires a running jack daemon
+    /// use moomoot::Capacities;
+    ///
+    /// // hundreds of voice mixers
+    /// let capacities = Capacities { mixers: 1024, sub_mixers: 512, ..Capacities::default() };
+    /// let mut moomoot = moomoot::MooMoot::start_with(capacities);
+    /// ```
+    pub fn start_with(capacities: Capacities) -> MooMoot {
         // 1. open a client
         let (client, _status) = j::Client::new("MooMoot", j::client_options::NO_START_SERVER)
             .unwrap();
         let sample_rate = client.sample_rate() as f64;
 
-        let (cmd_chan, process) = InternalProcess::new(&client);
+        let clock = Arc::new(AtomicUsize::new(0));
+        let (stats, recorder) = stats::recorder();
+        // (bounded : sending doesn't allocate, what doesn't fit waits in the
+        // tree's garbage)
+        let (freed_tx, freed) = sync_channel(1024);
+        let freed = garbage::collect(freed);
+        let failed = Arc::new(AtomicUsize::new(0));
+        let mut buses = BusSystem::new();
+        let (cmd_chan, mut process) = InternalProcess::new(
+            &client,
+            &capacities,
+            TransportBuses::new(&mut buses),
+            clock.clone(),
+            recorder,
+            freed_tx,
+            failed.clone(),
+        );
+        // 5 ms release ( see `set_release_time` )
+        process.synth_tree.set_release((0.005 * sample_rate) as usize);
+        let xruns = Arc::new(AtomicUsize::new(0));
+        let notifications = Notifications { xruns: xruns.clone() };
         // 4. activate the client
-        let active_client = j::AsyncClient::new(client, (), process).unwrap();
+        let active_client = j::AsyncClient::new(client, notifications, process).unwrap();
 
         MooMoot {
             async_client: active_client,
-            sample_rate: sample_rate,
+            sample_rate,
             send_channel: cmd_chan,
+            clock,
+            stats,
+            xruns,
+            failed,
+            registry: Registry::default(),
+            loaded: Loaded::with_capacities(capacities.clone()),
+            keys: Keys::with_capacity(capacities.mixers),
+            capacities,
+            buses: Arc::new(Mutex::new(buses)),
+            freed,
+            tracks: HashMap::new(),
         }
     }
     /// Disconnect from Jack
@@ -140,40 +410,555 @@ impl MooMoot {
 
     /// get an handle to the "root" mixer
     pub fn root_mixer(&self) -> MixerH {
-        MixerH("root".to_string())
+        MixerH(ROOT)
     }
 
-    /// create a mixer node.
-    pub fn add_mixer(&mut self, parent: &MixerH, name_prefix: &str) -> MixerH {
+    // a key for a new mixer ( the slots of the dropped ones are reused ),
+    // `None` when they are all in use
+    fn new_key(&mut self) -> Option<Key> {
+        self.collect_freed();
+        self.keys.allocate()
+    }
 
-        let mixer_id = format!("{}-{}", name_prefix, Uuid::new_v4().simple());
+    fn send_mixer<F>(&mut self, cmd: F) -> Option<MixerH>
+    where
+        F: FnOnce(Key) -> InternalCmd,
+    {
+        let key = self.new_key()?;
         self.send_channel
-            .send(InternalCmd::AddMixer(parent.0.clone(), mixer_id.clone()))
+            .send(cmd(key))
             .expect("can't send command to MooMoot (RT process stopped)");
-        MixerH(mixer_id)
+        Some(MixerH(key))
     }
 
-    /// add a synth to a mixer node.
-    pub fn add_synth<T: 'static + Synth>(&mut self, mixer: &MixerH, mut synth: T) {
-        synth.init(1. / self.sample_rate);
+    fn buses(&self) -> MutexGuard<'_, BusSystem> {
+        self.buses.lock().expect("can't lock the buses (a thread panicked)")
+    }
+
+    // parameters of the nodes connected to the buses, bus names resolved :
+    // the RT thread doesn't look anything up
+    fn connect(&self, cmd: &mut InternalCmd) {
+        let mut buses = self.buses();
+        match *cmd {
+            InternalCmd::AddSynth(_, ref mut synth, _) |
+            InternalCmd::AddNamedSynth(_, _, ref mut synth) => synth.connect_parameters(&mut buses),
+            InternalCmd::AddEfx(_, ref mut efx) |
+            InternalCmd::AddNamedEfx(_, _, ref mut efx) => efx.connect_parameters(&mut buses),
+            InternalCmd::SetNodeParameter(_, _, _, ref mut value) |
+            InternalCmd::AddSend(_, _, ref mut value) => value.connect(&mut buses),
+            InternalCmd::AddTrack(_, _, ref mut steps) |
+            InternalCmd::SetPattern(_, ref mut steps) => steps.resolve(&mut buses),
+            _ => {}
+        }
+    }
+
+    fn collect_freed(&mut self) {
+        while let Ok(idx) = self.freed.try_recv() {
+            self.keys.release(idx);
+        }
+    }
+
+    /// create a mixer node. ( `name` is for snapshots and `mixer_loads` )
+    ///
+    /// `None` when there's no room left for mixers ( see `Capacities` ). a
+    /// mixer refused by the RT thread ( its parent is gone or full ) counts
+    /// in `failed_commands`, its handle is then the one of a removed mixer.
+    pub fn add_mixer(&mut self, parent: &MixerH, name: &str) -> Option<MixerH> {
+        let mixer = Mixer::with_capacities(name, &self.capacities);
+        self.send_mixer(|key| InternalCmd::AddMixer(parent.0, key, mixer))
+    }
+
+    /// create a mixer that goes away by itself once it is silent ( think
+    /// one mixer per note, with its synths and effects ). its slot is
+    /// reused.
+    ///
+    /// it's silent until something plays in it : add its nodes right away.
+    /// # Example
+    /// ```no_run
+    ///  // requires a running jack daemon
+    /// use moomoot::synth::string::{KarplusStrong, KarplusStrongParams};
+    ///
+    /// let mut moomoot = moomoot::MooMoot::start();
+    /// let root_mixer = moomoot.root_mixer();
+    /// let note = moomoot.add_transient_mixer(&root_mixer, "note").unwrap();
+    /// moomoot.add_synth(&note, KarplusStrong::new(KarplusStrongParams::default().base_freq(220.)));
+    /// ```
+    pub fn add_transient_mixer(&mut self, parent: &MixerH, name: &str) -> Option<MixerH> {
+        let mixer = Mixer::with_capacities(name, &self.capacities).transient();
+        self.send_mixer(|key| InternalCmd::AddMixer(parent.0, key, mixer))
+    }
+
+    /// moves a mixer ( with its synths, effects, sends and their state )
+    /// under another parent. playing voices keep playing.
+    ///
+    /// does nothing for the root mixer or a return, and if `new_parent` is
+    /// the mixer or below it.
+    /// # Example
+    /// ```no_run
+    ///  // requires a running jack daemon
+    /// let mut moomoot = moomoot::MooMoot::start();
+    /// let root_mixer = moomoot.root_mixer();
+    /// let drums = moomoot.add_mixer(&root_mixer, "drums").unwrap();
+    /// let bus = moomoot.add_mixer(&root_mixer, "bus").unwrap();
+    /// moomoot.move_mixer(&drums, &bus);
+    /// ```
+    pub fn move_mixer(&mut self, mixer: &MixerH, new_parent: &MixerH) {
         self.send_channel
-            .send(InternalCmd::AddSynth(mixer.0.clone(), Box::new(synth)))
+            .send(InternalCmd::MoveMixer(mixer.0, new_parent.0))
             .expect("can't send command to MooMoot (RT process stopped)");
     }
 
-    /// add an effect to a mixer
-    pub fn add_efx<T: Efx + 'static>(&mut self, mixer: &MixerH, mut efx: T) {
-        efx.init(1. / self.sample_rate);
+    /// create a "return" mixer (think shared reverb or delay).
+    ///
+    /// it is fed by the sends of other mixers, and outputs directly to the
+    /// main output. ( `None` : see `add_mixer` )
+    pub fn add_return(&mut self, name: &str) -> Option<MixerH> {
+        let mixer = Mixer::with_capacities(name, &self.capacities);
+        self.send_mixer(|key| InternalCmd::AddReturn(key, mixer))
+    }
+
+    /// send `amount` of a mixer output (after its effects) to a return mixer.
+    pub fn add_send<T>(&mut self, mixer: &MixerH, return_mixer: &MixerH, amount: T)
+    where
+        ParamValue: From<T>,
+    {
+        let mut amount = ParamValue::from(amount);
+        amount.init(1. / self.sample_rate);
+        let mut cmd = InternalCmd::AddSend(mixer.0, return_mixer.0, amount);
+        self.connect(&mut cmd);
+        self.send_channel.send(cmd).expect(
+            "can't send command to MooMoot (RT process stopped)",
+        );
+    }
+
+    /// add a synth to a mixer node. a mixer plays 64 synths at most, the
+    /// others are dropped ( see `failed_commands` ).
+    pub fn add_synth<T: 'static + Synth>(&mut self, mixer: &MixerH, synth: T) {
+        self.add_boxed_synth(mixer, Box::new(synth));
+    }
+
+    /// add a synth built at runtime ( see `registry::Registry` )
+    pub fn add_boxed_synth(&mut self, mixer: &MixerH, synth: Box<dyn Synth>) {
+        let cmd = self.add_synth_cmd(mixer, synth, 0);
+        self.send_channel.send(cmd).expect(
+            "can't send command to MooMoot (RT process stopped)",
+        );
+    }
+
+    /// add a synth to a mixer node at `time` of the audio clock ( see `now` ):
+    /// it starts playing at this exact frame.
+    pub fn add_synth_at<T: 'static + Synth>(&mut self, mixer: &MixerH, synth: T, time: f64) {
+        let cmd = self.add_synth_cmd(mixer, Box::new(synth), 0);
+        self.send_at(time, cmd);
+    }
+
+    /// add a synth that is less likely to be stolen when the mixer is full
+    /// ( with `StealPolicy::LowestPriority`, the default priority is 0 )
+    pub fn add_synth_with_priority<T: 'static + Synth>(
+        &mut self,
+        mixer: &MixerH,
+        synth: T,
+        priority: i32,
+    ) {
+        let cmd = self.add_synth_cmd(mixer, Box::new(synth), priority);
+        self.send_channel.send(cmd).expect(
+            "can't send command to MooMoot (RT process stopped)",
+        );
+    }
+
+    /// cap the number of synths playing in a mixer : adding one more steals
+    /// a voice, which fades out ( see `set_release_time` ). `None` removes the
+    /// limit.
+    /// # Example
+    /// ```no_run
+    /// use moomoot::{StealPolicy, VoiceLimit};
+    ///  // requires a running jack daemon
+    /// let mut moomoot = moomoot::MooMoot::start();
+    /// let root_mixer = moomoot.root_mixer();
+    /// let strings = moomoot.add_mixer(&root_mixer, "strings").unwrap();
+    /// moomoot.set_voice_limit(&strings, Some(VoiceLimit {
+    ///     max: 8,
+    ///     policy: StealPolicy::Quietest,
+    /// }));
+    /// ```
+    pub fn set_voice_limit(&mut self, mixer: &MixerH, limit: Option<VoiceLimit>) {
+        self.send_channel
+            .send(InternalCmd::SetVoiceLimit(mixer.0, limit))
+            .expect("can't send command to MooMoot (RT process stopped)");
+    }
+
+    /// removed synths and mixers, stolen voices and synths stopping
+    /// mid-waveform are ramped to zero in `seconds` ( 5 ms by default ),
+    /// so they don't click. effects are still removed right away.
+    pub fn set_release_time(&mut self, seconds: f64) {
+        let frames = (seconds * self.sample_rate) as usize;
         self.send_channel
-            .send(InternalCmd::AddEfx(mixer.0.clone(), Box::new(efx)))
+            .send(InternalCmd::SetRelease(frames))
             .expect("can't send command to MooMoot (RT process stopped)");
     }
 
+    fn add_synth_cmd(&self, mixer: &MixerH, mut synth: Box<dyn Synth>, priority: i32) -> InternalCmd {
+        synth.init(1. / self.sample_rate);
+        synth.init_parameters(1. / self.sample_rate);
+        let mut cmd = InternalCmd::AddSynth(mixer.0, synth, priority);
+        self.connect(&mut cmd);
+        cmd
+    }
+
+    /// add an effect to a mixer
+    pub fn add_efx<T: Efx + 'static>(&mut self, mixer: &MixerH, efx: T) {
+        self.add_boxed_efx(mixer, Box::new(efx));
+    }
+
+    /// add an effect built at runtime ( see `registry::Registry` )
+    pub fn add_boxed_efx(&mut self, mixer: &MixerH, efx: Box<dyn Efx>) {
+        let cmd = self.add_efx_cmd(mixer, efx);
+        self.send_channel.send(cmd).expect(
+            "can't send command to MooMoot (RT process stopped)",
+        );
+    }
+
+    /// add an effect to a mixer at `time` of the audio clock
+    pub fn add_efx_at<T: Efx + 'static>(&mut self, mixer: &MixerH, efx: T, time: f64) {
+        let cmd = self.add_efx_cmd(mixer, Box::new(efx));
+        self.send_at(time, cmd);
+    }
+
+    fn add_efx_cmd(&self, mixer: &MixerH, mut efx: Box<dyn Efx>) -> InternalCmd {
+        efx.init(1. / self.sample_rate);
+        efx.init_parameters(1. / self.sample_rate);
+        let mut cmd = InternalCmd::AddEfx(mixer.0, efx);
+        self.connect(&mut cmd);
+        cmd
+    }
+
     /// set a new parameter value in the bus system
     pub fn set_bus_value(&mut self, bus: &str, value: f64) {
+        let bus = self.buses().bus(bus);
+        self.send_channel
+            .send(InternalCmd::SetBusValue(bus, value))
+            .expect("can't send command to MooMoot (RT process stopped)");
+    }
+
+    /// audio clock : time (in seconds) of the next frame to be computed.
+    ///
+    /// it moves one JACK period at a time, and stands still while following a
+    /// stopped transport ( see `follow_transport` ).
+    pub fn now(&self) -> f64 {
+        self.clock.load(Ordering::Relaxed) as f64 / self.sample_rate
+    }
+
+    /// set a bus value at `time` of the audio clock ( see `now` ). it's sample
+    /// accurate as long as `time` is at least one period ahead.
+    ///
+    /// at most 256 timed commands wait at once, the others are dropped
+    /// ( see `failed_commands` )
+    pub fn set_bus_value_at(&mut self, bus: &str, value: f64, time: f64) {
+        let cmd = InternalCmd::SetBusValue(self.buses().bus(bus), value);
+        self.send_at(time, cmd);
+    }
+
+    /// go linearly from the current bus value to `target` in `duration` seconds
+    ///
+    /// at most 32 ramps run at once, the others are dropped
+    /// ( see `failed_commands` )
+    pub fn ramp_bus(&mut self, bus: &str, target: f64, duration: f64) {
+        let cmd = self.ramp_cmd(bus, target, duration);
+        self.send_channel.send(cmd).expect(
+            "can't send command to MooMoot (RT process stopped)",
+        );
+    }
+
+    /// same as `ramp_bus`, starting at `time` of the audio clock
+    pub fn ramp_bus_at(&mut self, bus: &str, target: f64, duration: f64, time: f64) {
+        let cmd = self.ramp_cmd(bus, target, duration);
+        self.send_at(time, cmd);
+    }
+
+    fn ramp_cmd(&self, bus: &str, target: f64, duration: f64) -> InternalCmd {
+        let frames = (duration * self.sample_rate).round().max(0.) as usize;
+        InternalCmd::RampBus(self.buses().bus(bus), target, frames)
+    }
+
+    fn send_at(&mut self, time: f64, cmd: InternalCmd) {
+        let frame = (time * self.sample_rate).round().max(0.) as u64;
+        self.send_channel
+            .send(InternalCmd::At(frame, Box::new(cmd)))
+            .expect("can't send command to MooMoot (RT process stopped)");
+    }
+
+    /// play `pattern` in a loop, voices of `instrument` being added to `mixer`.
+    ///
+    /// the voices are built a few notes ahead in a thread of the track : the
+    /// instrument doesn't run in the RT thread.
+    /// # Example
+    /// ```no_run
+    /// use moomoot::sequencer::Pattern;
+    /// use moomoot::synth::string::{KarplusStrong, KarplusStrongParams};
+    ///  // requires a running jack daemon
+    /// let mut moomoot = moomoot::MooMoot::start();
+    /// let root_mixer = moomoot.root_mixer();
+    /// moomoot.set_tempo(90.);
+    /// let track = moomoot.add_track(
+    ///     &root_mixer,
+    ///     |f| Box::new(KarplusStrong::new(KarplusStrongParams::default().base_freq(f))) as Box<_>,
+    ///     Pattern::from_notes(2, &[Some(110.), None, Some(165.), Some(220.)]),
+    /// );
+    /// ```
+    pub fn add_track<I: Instrument + 'static>(
+        &mut self,
+        mixer: &MixerH,
+        instrument: I,
+        pattern: Pattern,
+    ) -> TrackH {
+        let track_id = format!("track-{}", Uuid::new_v4().simple());
+        let maker = VoiceMaker::new(Box::new(instrument), 1. / self.sample_rate, self.buses.clone())
+            .spawn();
+        let mut cmd = InternalCmd::AddTrack(track_id.clone(), mixer.0, maker.steps(&pattern));
+        self.connect(&mut cmd);
+        self.tracks.insert(track_id.clone(), maker);
+        self.send_channel.send(cmd).expect(
+            "can't send command to MooMoot (RT process stopped)",
+        );
+        TrackH(track_id)
+    }
+
+    /// swap the pattern of a track (from its next step, it stays in sync)
+    pub fn set_pattern(&mut self, track: &TrackH, pattern: Pattern) {
+        let steps = match self.tracks.get(&track.0) {
+            Some(maker) => maker.steps(&pattern),
+            None => return,
+        };
+        let mut cmd = InternalCmd::SetPattern(track.0.clone(), steps);
+        self.connect(&mut cmd);
+        self.send_channel.send(cmd).expect(
+            "can't send command to MooMoot (RT process stopped)",
+        );
+    }
+
+    /// stop a track (playing voices keep on until they are done)
+    pub fn remove_track(&mut self, track: TrackH) {
+        self.tracks.remove(&track.0);
+        self.send_channel
+            .send(InternalCmd::RemoveTrack(track.0))
+            .expect("can't send command to MooMoot (RT process stopped)");
+    }
+
+    /// sequencer tempo, in beats per minute (default 120)
+    pub fn set_tempo(&mut self, bpm: f64) {
+        self.send_channel.send(InternalCmd::SetTempo(bpm)).expect(
+            "can't send command to MooMoot (RT process stopped)",
+        );
+    }
+
+    /// sequencer swing : odd steps are late by this fraction of a step
+    /// (0 : straight, 1/3 : triplets feel)
+    pub fn set_swing(&mut self, swing: f64) {
+        self.send_channel.send(InternalCmd::SetSwing(swing)).expect(
+            "can't send command to MooMoot (RT process stopped)",
+        );
+    }
+
+    /// follow JACK transport : the sequencer starts and stops with it, and
+    /// takes its tempo and position when there's a timebase master.
+    ///
+    /// while it's stopped, the audio clock stands still too ( `now` ) : timed
+    /// commands and bus ramps wait for it to roll again. the sound goes on :
+    /// playing voices, the modulation effects and the time functions of the
+    /// formulas ( `lag`, `smooth` ) keep running, so that tails aren't cut.
+    ///
+    /// (the transport state is always published on the `transport.rolling`,
+    /// `transport.bpm`, `transport.bar` and `transport.beat` buses)
+    pub fn follow_transport(&mut self, follow: bool) {
+        self.send_channel
+            .send(InternalCmd::FollowTransport(follow))
+            .expect("can't send command to MooMoot (RT process stopped)");
+    }
+
+    /// node types for `load_patch` (register your own synths and effects here)
+    pub fn registry_mut(&mut self) -> &mut Registry {
+        &mut self.registry
+    }
+
+    /// create the mixers and nodes of a patch ( see `patch` ). its mixers can
+    /// be used by the next patches, and by `patch_mixer`.
+    ///
+    /// the whole patch is checked first : nothing is created if it's invalid.
+    /// # Example
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::io::Read;
+    ///  // requires a running jack daemon
+    /// let mut moomoot = moomoot::MooMoot::start();
+    /// let mut text = String::new();
+    /// File::open("drone.patch").unwrap().read_to_string(&mut text).unwrap();
+    /// moomoot.load_patch(&text.parse().unwrap()).unwrap();
+    /// ```
+    pub fn load_patch(&mut self, patch: &Patch) -> Result<(), PatchError> {
+        let mut all = self.loaded.patch().clone();
+        all.statements.extend(patch.statements.iter().cloned());
+        self.reload_patch(&all)
+    }
+
+    /// replace what was loaded with `load_patch` or `reload_patch` with this
+    /// patch. only what changed is touched ( see `patch::diff` ) : playing
+    /// synths, effect states and bus values are kept.
+    ///
+    /// nothing changes if the patch is invalid.
+    /// # Example
+    /// ```no_run
+    /// use std::thread::sleep;
+    /// use std::time::Duration;
+    /// use moomoot::patch::Watcher;
+    ///  // requires a running jack daemon
+    /// let mut moomoot = moomoot::MooMoot::start();
+    /// let mut watcher = Watcher::new("live.patch");
+    /// loop {
+    ///     match watcher.poll() {
+    ///         Some(Ok(patch)) => moomoot.reload_patch(&patch).unwrap_or_else(|e| println!("{}", e)),
+    ///         Some(Err(e)) => println!("{}", e),
+    ///         None => (),
+    ///     }
+    ///     sleep(Duration::from_millis(200));
+    /// }
+    /// ```
+    pub fn reload_patch(&mut self, patch: &Patch) -> Result<(), PatchError> {
+        self.collect_freed();
+        let mut keys = self.keys.clone();
+        let (loaded, cmds) =
+            self.loaded.reload(patch, &self.registry, &mut keys, 1. / self.sample_rate)?;
+
+        for mut cmd in cmds {
+            self.connect(&mut cmd);
+            self.send_channel.send(cmd).expect(
+                "can't send command to MooMoot (RT process stopped)",
+            );
+        }
+        self.keys = keys;
+        self.loaded = loaded;
+        Ok(())
+    }
+
+    /// handle of a mixer declared in a loaded patch
+    pub fn patch_mixer(&self, name: &str) -> Option<MixerH> {
+        self.loaded.mixer(name).map(MixerH)
+    }
+
+    /// the live tree as a patch, to save it : what was loaded from patches
+    /// and what was added from the API ( see `Patch::from` ). `None` if the
+    /// RT thread doesn't answer.
+    /// # Example
+    /// ```no_run
+    ///  // requires a running jack daemon
+    /// use std::fs::File;
+    /// use std::io::Write;
+    ///
+    /// let mut moomoot = moomoot::MooMoot::start();
+    /// let lead = moomoot.add_mixer(&moomoot.root_mixer(), "lead").unwrap();
+    /// moomoot.add_synth(&lead, moomoot::synth::sine::Sine::new(Default::default()));
+    /// let patch = moomoot.patch().unwrap();
+    /// write!(File::create("saved.patch").unwrap(), "{}", patch).unwrap();
+    /// ```
+    pub fn patch(&self) -> Option<Patch> {
+        self.snapshot().map(|snapshot| Patch::from(&snapshot))
+    }
+
+    /// what the tree looks like now : mixers, nodes with their parameter
+    /// values, sends and buses. `None` if the RT thread doesn't answer.
+    ///
+    /// it's built in the RT thread ( allocations ) : not for every frame.
+    pub fn snapshot(&self) -> Option<TreeSnapshot> {
+        let (reply, answer) = channel();
+        self.send_channel
+            .send(InternalCmd::Snapshot(reply))
+            .expect("can't send command to MooMoot (RT process stopped)");
+        let mut snapshot = answer.recv_timeout(Duration::from_secs(1)).ok()?;
+        snapshot.buses = self.buses().snapshot();
+        Some(snapshot)
+    }
+
+    /// peak and RMS levels of a mixer output, after its effects
+    /// # Example
+    /// ```no_run
+    ///  // requires a running jack daemon
+    /// let mut moomoot = moomoot::MooMoot::start();
+    /// let root_mixer = moomoot.root_mixer();
+    /// let meter = moomoot.meter(&root_mixer);
+    /// println!("{:.1} dB", meter.peak_db());
+    /// ```
+    pub fn meter(&mut self, mixer: &MixerH) -> Meter {
+        let (meter, probe) = self.new_meter();
+        self.send_channel
+            .send(InternalCmd::AddMeter(Some(mixer.0), probe))
+            .expect("can't send command to MooMoot (RT process stopped)");
+        meter
+    }
+
+    /// levels of the main output ( the root mixer and the returns )
+    pub fn output_meter(&mut self) -> Meter {
+        let (meter, probe) = self.new_meter();
+        self.send_channel
+            .send(InternalCmd::AddMeter(None, probe))
+            .expect("can't send command to MooMoot (RT process stopped)");
+        meter
+    }
+
+    fn new_meter(&self) -> (Meter, MeterProbe) {
+        // 50 ms windows
+        meter::meter((0.05 * self.sample_rate) as usize)
+    }
+
+    /// DSP load of the RT thread, with the tree size and the xruns so far
+    /// # Example
+    /// ```no_run
+    ///  // requires a running jack daemon
+    /// let moomoot = moomoot::MooMoot::start();
+    /// let stats = moomoot.dsp_stats();
+    /// println!("load {:.0}% (max {:.0}%), {} xruns",
+    ///          stats.load_avg * 100., stats.load_max * 100., stats.xruns);
+    /// ```
+    pub fn dsp_stats(&self) -> DspStats {
+        // (JACK gives a percentage)
+        let jack_load = self.async_client.cpu_load() as f64 / 100.;
+        stats::dsp_stats(self.stats.value(), jack_load, self.xruns.load(Ordering::Relaxed))
+    }
+
+    /// commands the RT thread couldn't apply so far : a handle to a removed
+    /// mixer, a move that would make a cycle, a full mixer ..
+    pub fn failed_commands(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// measure each top level mixer ( the root mixer's children and the
+    /// returns ), for `mixer_loads`. costs a bit of DSP itself.
+    pub fn profile_mixers(&mut self, profiling: bool) {
+        self.send_channel
+            .send(InternalCmd::ProfileMixers(profiling))
+            .expect("can't send command to MooMoot (RT process stopped)");
+    }
+
+    /// DSP load of each top level mixer ( with everything below it ) since
+    /// the last call, by mixer id. empty when not profiling, `None` if the
+    /// RT thread doesn't answer.
+    pub fn mixer_loads(&self) -> Option<Vec<(String, f64)>> {
+        let (reply, answer) = channel();
         self.send_channel
-            .send(InternalCmd::SetBusValue(bus.to_string(), value))
+            .send(InternalCmd::MixerTimes(reply))
             .expect("can't send command to MooMoot (RT process stopped)");
+        answer.recv_timeout(Duration::from_secs(1)).ok().map(|(frames, times)| {
+            let duration = frames as f64 / self.sample_rate;
+            times
+                .into_iter()
+                .map(|(id, time)| {
+                    let load = if frames > 0 {
+                        stats::seconds(time) / duration
+                    } else {
+                        0.
+                    };
+                    (id, load)
+                })
+                .collect()
+        })
     }
 
     /// returns sampling rate ( in seconds )
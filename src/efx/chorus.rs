@@ -0,0 +1,56 @@
+use traits::{SoundSample, SampleValue};
+use params::*;
+use super::Efx;
+use super::moddelay::ModulatedDelay;
+
+// delay and depth are in seconds, rate in Hz.
+declare_params!(ChorusParams {
+    rate: 0.8 => ParamRange::new(0.01, 20.).unit("Hz").exponential(),
+    depth: 0.003 => ParamRange::new(0., 0.05).unit("s"),
+    delay: 0.02 => ParamRange::new(0., 0.1).unit("s"),
+    feedback: 0.0 => ParamRange::new(-0.99, 0.99),
+    mix: 0.5 => ParamRange::new(0., 1.),
+});
+
+/// Chorus : the signal mixed with a copy delayed by a few tens of ms,
+/// the delay slowly wobbling around.
+pub struct Chorus {
+    params: ChorusParams,
+    delay_line: ModulatedDelay,
+}
+
+impl Parametrized for Chorus {
+    fn get_parameters(&mut self) -> &mut dyn Parameters {
+        &mut self.params
+    }
+}
+
+impl Chorus {
+    pub fn new(params: ChorusParams) -> Chorus {
+        Chorus {
+            params,
+            delay_line: ModulatedDelay::new(0.1),
+        }
+    }
+}
+
+impl Efx for Chorus {
+    fn init(&mut self, frame_t: f64) {
+        self.delay_line.init(frame_t);
+    }
+
+    fn sample(&mut self, sample: SampleValue) -> SoundSample {
+        let settings = self.delay_line.settings(
+            self.params.delay.value(),
+            self.params.depth.value(),
+            self.params.rate.value(),
+            self.params.feedback.value(),
+            self.params.mix.value(),
+        );
+        self.delay_line.sample(sample, &settings)
+    }
+
+    fn type_name(&self) -> &str {
+        "chorus"
+    }
+}
@@ -0,0 +1,56 @@
+use traits::{SoundSample, SampleValue};
+use params::*;
+use super::Efx;
+use super::moddelay::ModulatedDelay;
+
+// delay and depth are in seconds, rate in Hz.
+declare_params!(FlangerParams {
+    rate: 0.2 => ParamRange::new(0.01, 20.).unit("Hz").exponential(),
+    depth: 0.002 => ParamRange::new(0., 0.01).unit("s"),
+    delay: 0.0025 => ParamRange::new(0., 0.02).unit("s"),
+    feedback: 0.7 => ParamRange::new(-0.99, 0.99),
+    mix: 0.5 => ParamRange::new(0., 1.),
+});
+
+/// Flanger : like a chorus, but with a very short delay and a strong
+/// feedback (gives the comb filter "jet" sweep)
+pub struct Flanger {
+    params: FlangerParams,
+    delay_line: ModulatedDelay,
+}
+
+impl Parametrized for Flanger {
+    fn get_parameters(&mut self) -> &mut dyn Parameters {
+        &mut self.params
+    }
+}
+
+impl Flanger {
+    pub fn new(params: FlangerParams) -> Flanger {
+        Flanger {
+            params,
+            delay_line: ModulatedDelay::new(0.02),
+        }
+    }
+}
+
+impl Efx for Flanger {
+    fn init(&mut self, frame_t: f64) {
+        self.delay_line.init(frame_t);
+    }
+
+    fn sample(&mut self, sample: SampleValue) -> SoundSample {
+        let settings = self.delay_line.settings(
+            self.params.delay.value(),
+            self.params.depth.value(),
+            self.params.rate.value(),
+            self.params.feedback.value(),
+            self.params.mix.value(),
+        );
+        self.delay_line.sample(sample, &settings)
+    }
+
+    fn type_name(&self) -> &str {
+        "flanger"
+    }
+}
@@ -1,5 +1,9 @@
 pub mod volume;
 pub mod pan;
+pub mod chorus;
+pub mod flanger;
+pub mod phaser;
+mod moddelay;
 use traits::{SampleValue, SoundSample};
 use params::Parametrized;
 
@@ -12,4 +16,9 @@ pub trait Efx: Parametrized + Send {
     fn init(&mut self, frame_t: f64) {}
 
     fn sample(&mut self, smpl: SampleValue) -> SoundSample;
+
+    /// name of the effect type ( see `Synth::type_name` )
+    fn type_name(&self) -> &str {
+        "unknown"
+    }
 }
@@ -0,0 +1,111 @@
+use traits::{SoundSample, SampleValue, mono_value, stereo_value};
+use utils::ringbuffer::FixedRingBuffer;
+use utils::lfo::Lfo;
+
+/// one channel of a modulated delay line (with feedback)
+struct DelayChannel {
+    line: FixedRingBuffer,
+    lfo: Lfo,
+    last: f64, // last delayed value, fed back into the line
+}
+
+impl DelayChannel {
+    fn new(lfo_phase: f64) -> DelayChannel {
+        DelayChannel {
+            line: FixedRingBuffer::from(vec![0.; 1]),
+            lfo: Lfo::new(lfo_phase),
+            last: 0.,
+        }
+    }
+
+    fn init(&mut self, frame_t: f64, max_delay: f64) {
+        let len = (max_delay / frame_t) as usize + 2;
+        self.line = FixedRingBuffer::from(vec![0.; len]);
+        self.lfo.init(frame_t);
+        self.last = 0.;
+    }
+
+    fn process(&mut self, x: f64, settings: &DelaySettings) -> f64 {
+        let mut input = x + settings.feedback * self.last;
+        self.line.queue(&mut input);
+
+        let delay = settings.delay + settings.depth * self.lfo.next(settings.rate);
+        self.last = self.line.read_frac(delay / settings.frame_t);
+
+        (1. - settings.mix) * x + settings.mix * self.last
+    }
+}
+
+/// per frame values of the effect parameters
+pub struct DelaySettings {
+    /// center delay (s)
+    pub delay: f64,
+    /// modulation depth (s)
+    pub depth: f64,
+    /// lfo rate (Hz)
+    pub rate: f64,
+    pub feedback: f64,
+    /// dry (0.) / wet (1.) balance
+    pub mix: f64,
+    frame_t: f64,
+}
+
+/// Common part of chorus and flanger : a delay line which tap is swept
+/// by an lfo, mixed back with the dry signal.
+///
+/// stereo signals get one delay line per side, with the left lfo a quarter
+/// period late (widens the image).
+pub struct ModulatedDelay {
+    right: DelayChannel,
+    left: DelayChannel,
+    max_delay: f64,
+    frame_t: f64,
+}
+
+impl ModulatedDelay {
+    /// `max_delay` (s) : the longest delay the line has to hold
+    pub fn new(max_delay: f64) -> ModulatedDelay {
+        ModulatedDelay {
+            right: DelayChannel::new(0.),
+            left: DelayChannel::new(0.25),
+            max_delay,
+            frame_t: 0.,
+        }
+    }
+
+    pub fn init(&mut self, frame_t: f64) {
+        self.frame_t = frame_t;
+        self.right.init(frame_t, self.max_delay);
+        self.left.init(frame_t, self.max_delay);
+    }
+
+    pub fn settings(&self, delay: f64, depth: f64, rate: f64, feedback: f64, mix: f64) -> DelaySettings {
+        let delay = delay.max(0.).min(self.max_delay);
+        DelaySettings {
+            delay,
+            depth: depth.max(0.).min(delay.min(self.max_delay - delay)),
+            rate,
+            // above 1. it blows up.
+            feedback: feedback.clamp(-0.99, 0.99),
+            mix: mix.clamp(0., 1.),
+            frame_t: self.frame_t,
+        }
+    }
+
+    pub fn sample(&mut self, smpl: SampleValue, settings: &DelaySettings) -> SoundSample {
+        if self.frame_t == 0. {
+            // not initialized : pass through
+            return SoundSample::Sample(smpl);
+        }
+
+        match smpl {
+            SampleValue::Mono(x) => mono_value(self.right.process(x, settings)),
+            SampleValue::Stereo(r, l) => {
+                stereo_value(
+                    self.right.process(r, settings),
+                    self.left.process(l, settings),
+                )
+            }
+        }
+    }
+}
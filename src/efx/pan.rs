@@ -3,21 +3,21 @@ use params::*;
 use super::Efx;
 
 // 0. => all right 1. => all left
-declare_params!(PanParams { pan: 0.5 });
+declare_params!(PanParams { pan: 0.5 => ParamRange::new(0., 1.) });
 
 pub struct Pan {
     params: PanParams,
 }
 
 impl Parametrized for Pan {
-    fn get_parameters(&mut self) -> &mut Parameters {
+    fn get_parameters(&mut self) -> &mut dyn Parameters {
         &mut self.params
     }
 }
 
 impl Pan {
     pub fn new(params: PanParams) -> Pan {
-        Pan { params: params }
+        Pan { params }
     }
 }
 
@@ -32,4 +32,8 @@ impl Efx for Pan {
             SampleValue::Stereo(r, l) => stereo_value(right_v * r, left_v * l), // ??
         }
     }
+
+    fn type_name(&self) -> &str {
+        "pan"
+    }
 }
@@ -0,0 +1,124 @@
+use std::f64::consts::PI;
+use traits::{SoundSample, SampleValue, mono_value, stereo_value};
+use utils::lfo::Lfo;
+use params::*;
+use super::Efx;
+
+// rate in Hz, depth is the fraction of the [min_freq, max_freq] range swept.
+declare_params!(PhaserParams {
+    rate: 0.5 => ParamRange::new(0.01, 20.).unit("Hz").exponential(),
+    depth: 1.0 => ParamRange::new(0., 1.),
+    min_freq: 200.0 => ParamRange::new(20., 20000.).unit("Hz").exponential(),
+    max_freq: 2000.0 => ParamRange::new(20., 20000.).unit("Hz").exponential(),
+    feedback: 0.5 => ParamRange::new(-0.99, 0.99),
+    mix: 0.5 => ParamRange::new(0., 1.),
+});
+
+const STAGES: usize = 6;
+
+/// first order all pass filter
+#[derive(Default, Clone, Copy)]
+struct AllPass {
+    x1: f64,
+    y1: f64,
+}
+
+impl AllPass {
+    fn process(&mut self, x: f64, coef: f64) -> f64 {
+        let y = coef * x + self.x1 - coef * self.y1;
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
+}
+
+/// one channel : a cascade of all pass stages with feedback
+#[derive(Default)]
+struct PhaserChannel {
+    stages: [AllPass; STAGES],
+    last: f64,
+}
+
+impl PhaserChannel {
+    fn process(&mut self, x: f64, coef: f64, feedback: f64, mix: f64) -> f64 {
+        let mut y = x + feedback * self.last;
+        for stage in self.stages.iter_mut() {
+            y = stage.process(y, coef);
+        }
+        self.last = y;
+        (1. - mix) * x + mix * y
+    }
+}
+
+/// Phaser : all pass filters cascade, which break frequency is swept by an
+/// lfo. Mixed with the dry signal, it sweeps notches across the spectrum.
+pub struct Phaser {
+    params: PhaserParams,
+    lfo: Lfo,
+    frame_t: f64,
+    right: PhaserChannel,
+    left: PhaserChannel,
+}
+
+impl Parametrized for Phaser {
+    fn get_parameters(&mut self) -> &mut dyn Parameters {
+        &mut self.params
+    }
+}
+
+impl Phaser {
+    pub fn new(params: PhaserParams) -> Phaser {
+        Phaser {
+            params,
+            lfo: Lfo::new(0.),
+            frame_t: 0.,
+            right: PhaserChannel::default(),
+            left: PhaserChannel::default(),
+        }
+    }
+
+    // all pass coefficient for the current lfo position
+    fn coefficient(&mut self) -> f64 {
+        let min_freq = self.params.min_freq.value().max(1.);
+        let max_freq = self.params.max_freq.value().max(min_freq);
+        let depth = self.params.depth.value().clamp(0., 1.);
+        let sweep = (self.lfo.next(self.params.rate.value()) + 1.) * 0.5 * depth;
+        // sweep exponentially ( our ears are logarithmic )
+        let freq = (min_freq * (max_freq / min_freq).powf(sweep)).min(0.49 / self.frame_t);
+
+        let t = (PI * freq * self.frame_t).tan();
+        (t - 1.) / (t + 1.)
+    }
+}
+
+impl Efx for Phaser {
+    fn init(&mut self, frame_t: f64) {
+        self.frame_t = frame_t;
+        self.lfo.init(frame_t);
+    }
+
+    fn sample(&mut self, sample: SampleValue) -> SoundSample {
+        if self.frame_t == 0. {
+            // not initialized : pass through
+            return SoundSample::Sample(sample);
+        }
+
+        let coef = self.coefficient();
+        let feedback = self.params.feedback.value().clamp(-0.99, 0.99);
+        let mix = self.params.mix.value().clamp(0., 1.);
+
+        match sample {
+            SampleValue::Mono(x) => mono_value(self.right.process(x, coef, feedback, mix)),
+            SampleValue::Stereo(r, l) => {
+                stereo_value(
+                    self.right.process(r, coef, feedback, mix),
+                    self.left.process(l, coef, feedback, mix),
+                )
+            }
+        }
+    }
+
+    fn type_name(&self) -> &str {
+        "phaser"
+    }
+}
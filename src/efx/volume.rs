@@ -3,14 +3,14 @@ use params::*;
 use super::Efx;
 
 
-declare_params!(VolumeParams { volume: 1.0 });
+declare_params!(VolumeParams { volume: 1.0 => ParamRange::new(0., 4.) });
 
 pub struct Volume {
     params: VolumeParams,
 }
 
 impl Parametrized for Volume {
-    fn get_parameters(&mut self) -> &mut Parameters {
+    fn get_parameters(&mut self) -> &mut dyn Parameters {
         &mut self.params
     }
 }
@@ -18,20 +18,21 @@ impl Parametrized for Volume {
 
 impl Volume {
     pub fn new(params: VolumeParams) -> Volume {
-        Volume { params: params }
+        Volume { params }
     }
 }
 
 impl Efx for Volume {
     fn sample(&mut self, sample: SampleValue) -> SoundSample {
+        // once per frame (formulas may have a state)
+        let volume = self.params.volume.value();
         match sample {
-            SampleValue::Mono(x) => mono_value(self.params.volume.value() * x),
-            SampleValue::Stereo(r, l) => {
-                stereo_value(
-                    self.params.volume.value() * r,
-                    self.params.volume.value() * l,
-                )
-            }
+            SampleValue::Mono(x) => mono_value(volume * x),
+            SampleValue::Stereo(r, l) => stereo_value(volume * r, volume * l),
         }
     }
+
+    fn type_name(&self) -> &str {
+        "volume"
+    }
 }
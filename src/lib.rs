@@ -20,22 +20,36 @@ limitations under the License.
 //! where leaves are indiviual "unit" synthesiser that gets mixed and applied effects
 //! down the tree. Each effect or unit synthesiser's parameters can get changed in real
 //! time via an internal parameter bus system.
-#![feature(test, drain_filter)]
+#![cfg_attr(feature = "nightly", feature(test))]
 
 extern crate uuid;
 extern crate jack;
+extern crate jack_sys;
 #[macro_use]
 extern crate nom;
 
 mod base;
+mod loader;
 mod traits;
 pub mod param_expression;
 #[macro_use]
 mod params;
 pub mod synth;
 pub mod efx;
+pub mod registry;
+pub mod patch;
+pub mod snapshot;
+pub mod sequencer;
 mod tree;
+mod stats;
+mod transport;
 mod utils;
 
 pub use base::MooMoot;
+pub use tree::mmtree::Capacities;
+pub use tree::meter::Meter;
+pub use tree::voices::{StealPolicy, VoiceLimit};
+pub use stats::DspStats;
 pub use traits::SoundSample;
+pub use params::{bus, ParamValue, Parameters, Parametrized, ParamDesc, ParamRange, Curve,
+                 ParamError};
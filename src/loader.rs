@@ -0,0 +1,297 @@
+//! Patches to RT commands : what `MooMoot::reload_patch` sends for the
+//! changes between the loaded patch and a new one ( see `patch::diff` ).
+
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+use base::InternalCmd;
+use params::ParamValue;
+use patch::{diff, param_value, Change, Node, Patch, PatchError, Statement};
+use registry::Registry;
+use synth::Synth;
+use efx::Efx;
+use tree::mixer::Mixer;
+use tree::mmtree::Capacities;
+use tree::arena::{Key, Keys, ROOT};
+
+/// the loaded patch, with the mixers and nodes made for it
+#[derive(Clone)]
+pub struct Loaded {
+    patch: Patch,
+    capacities: Capacities,
+    mixers: HashMap<String, Key>,              // name -> mixer
+    nodes: HashMap<(String, String), String>, // (mixer, key) -> node id
+}
+
+impl Loaded {
+    #[cfg(test)]
+    pub fn new() -> Loaded {
+        Loaded::with_capacities(Capacities::default())
+    }
+
+    /// ( the mixers are made with the room of the tree )
+    pub fn with_capacities(capacities: Capacities) -> Loaded {
+        let mut mixers = HashMap::new();
+        mixers.insert("root".to_string(), ROOT);
+        Loaded {
+            patch: Patch::default(),
+            capacities,
+            mixers,
+            nodes: HashMap::new(),
+        }
+    }
+
+    pub fn patch(&self) -> &Patch {
+        &self.patch
+    }
+
+    pub fn mixer(&self, name: &str) -> Option<Key> {
+        self.mixers.get(name).cloned()
+    }
+
+    /// the commands going from this patch to `patch`, and what is loaded
+    /// once they're sent. nothing is sent if the patch is invalid : the
+    /// nodes are checked with the registry here, the RT side can't fail.
+    pub fn reload(
+        &self,
+        patch: &Patch,
+        registry: &Registry,
+        keys: &mut Keys,
+        frame_t: f64,
+    ) -> Result<(Loaded, Vec<InternalCmd>), PatchError> {
+        let changes = diff(&self.patch, patch)?;
+
+        let mut loaded = self.clone();
+        let mut cmds = Vec::new();
+        for change in changes.iter() {
+            cmds.extend(loaded.change_cmds(registry, keys, frame_t, change)?);
+        }
+
+        // forget the removed mixers, and what was in them
+        let names: HashSet<&str> = patch
+            .statements
+            .iter()
+            .filter_map(|st| match *st {
+                Statement::Mixer { ref name, .. } |
+                Statement::Return { ref name } => Some(name.as_str()),
+                _ => None,
+            })
+            .chain(Some("root"))
+            .collect();
+        loaded.mixers.retain(|name, _| names.contains(name.as_str()));
+        loaded.nodes.retain(|(mixer, _), _| names.contains(mixer.as_str()));
+        loaded.patch = patch.clone();
+        Ok((loaded, cmds))
+    }
+
+    fn mixer_id(&self, name: &str) -> Result<Key, PatchError> {
+        self.mixer(name).ok_or_else(
+            || PatchError::UnknownMixer(name.to_string()),
+        )
+    }
+
+    fn node_id(&self, mixer: &str, key: &str) -> Option<String> {
+        self.nodes.get(&(mixer.to_string(), key.to_string())).cloned()
+    }
+
+    fn new_mixer(&mut self, keys: &mut Keys, name: &str) -> Result<(Key, Mixer), PatchError> {
+        let key = keys.allocate().ok_or(PatchError::TooManyMixers)?;
+        self.mixers.insert(name.to_string(), key);
+        self.nodes.retain(|(mixer, _), _| mixer != name);
+        Ok((key, Mixer::with_capacities(name, &self.capacities)))
+    }
+
+    fn new_node(&mut self, mixer: &str, key: &str) -> String {
+        let id = format!("{}-{}", key, Uuid::new_v4().simple());
+        self.nodes.insert((mixer.to_string(), key.to_string()), id.clone());
+        id
+    }
+
+    fn change_cmds(
+        &mut self,
+        registry: &Registry,
+        keys: &mut Keys,
+        frame_t: f64,
+        change: &Change,
+    ) -> Result<Vec<InternalCmd>, PatchError> {
+        match *change {
+            Change::RemoveMixer { ref name } => {
+                self.mixer_id(name).map(|id| vec![InternalCmd::RemoveMixer(id)])
+            }
+            Change::RemoveNode { ref mixer, ref key } => {
+                let mixer_id = self.mixer_id(mixer)?;
+                Ok(
+                    self.node_id(mixer, key)
+                        .map(|id| InternalCmd::RemoveNode(mixer_id, id))
+                        .into_iter()
+                        .collect(),
+                )
+            }
+            Change::RemoveSend { ref from, ref to } => {
+                Ok(vec![
+                    InternalCmd::RemoveSend(self.mixer_id(from)?, self.mixer_id(to)?),
+                ])
+            }
+            Change::AddMixer {
+                ref name,
+                ref parent,
+            } => {
+                let parent_id = self.mixer_id(parent)?;
+                let (key, mixer) = self.new_mixer(keys, name)?;
+                Ok(vec![InternalCmd::AddMixer(parent_id, key, mixer)])
+            }
+            Change::AddReturn { ref name } => {
+                let (key, mixer) = self.new_mixer(keys, name)?;
+                Ok(vec![InternalCmd::AddReturn(key, mixer)])
+            }
+            Change::AddSynth {
+                ref mixer,
+                ref key,
+                ref node,
+            } => {
+                let mixer_id = self.mixer_id(mixer)?;
+                let mut synth = synth(registry, mixer, node)?;
+                synth.init(frame_t);
+                synth.init_parameters(frame_t);
+                let id = self.new_node(mixer, key);
+                Ok(vec![InternalCmd::AddNamedSynth(mixer_id, id, synth)])
+            }
+            Change::AddEfx {
+                ref mixer,
+                ref key,
+                ref node,
+            } => {
+                let mixer_id = self.mixer_id(mixer)?;
+                let mut efx = efx(registry, mixer, node)?;
+                efx.init(frame_t);
+                efx.init_parameters(frame_t);
+                let id = self.new_node(mixer, key);
+                Ok(vec![InternalCmd::AddNamedEfx(mixer_id, id, efx)])
+            }
+            Change::SetSynthParams {
+                ref mixer,
+                ref key,
+                ref node,
+                ref params,
+            } |
+            Change::SetEfxParams {
+                ref mixer,
+                ref key,
+                ref node,
+                ref params,
+            } => {
+                let mixer_id = self.mixer_id(mixer)?;
+                let descriptors = match *change {
+                    Change::SetSynthParams { .. } => {
+                        synth(registry, mixer, node).map(|_| registry.synth_params(&node.kind))
+                    }
+                    _ => efx(registry, mixer, node).map(|_| registry.efx_params(&node.kind)),
+                };
+                let descriptors = descriptors?.unwrap_or(&[]);
+                let id = match self.node_id(mixer, key) {
+                    Some(id) => id,
+                    None => return Ok(Vec::new()),
+                };
+
+                Ok(
+                    params
+                        .iter()
+                        .filter_map(|(name, value)| {
+                            // (the RT side gets the index, and a clamped value)
+                            let idx = descriptors.iter().position(|d| d.name == name)?;
+                            let desc = &descriptors[idx];
+                            let mut value = match *value {
+                                Some(ref value) => desc.clamp(param_value(value)),
+                                None => ParamValue::default(desc.default),
+                            };
+                            value.init(frame_t);
+                            Some(InternalCmd::SetNodeParameter(mixer_id, id.clone(), idx, value))
+                        })
+                        .collect(),
+                )
+            }
+            Change::SetSend {
+                ref from,
+                ref to,
+                ref amount,
+            } => {
+                let (from, to) = (self.mixer_id(from)?, self.mixer_id(to)?);
+                let mut amount = param_value(amount);
+                amount.init(frame_t);
+                Ok(vec![
+                    InternalCmd::RemoveSend(from, to),
+                    InternalCmd::AddSend(from, to, amount),
+                ])
+            }
+        }
+    }
+}
+
+fn synth(registry: &Registry, mixer: &str, node: &Node) -> Result<Box<dyn Synth>, PatchError> {
+    registry.synth(&node.kind, node.named_params()).map_err(|err| {
+        PatchError::Node(mixer.to_string(), err)
+    })
+}
+
+fn efx(registry: &Registry, mixer: &str, node: &Node) -> Result<Box<dyn Efx>, PatchError> {
+    registry.efx(&node.kind, node.named_params()).map_err(|err| {
+        PatchError::Node(mixer.to_string(), err)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reload(
+        loaded: &Loaded,
+        keys: &mut Keys,
+        text: &str,
+    ) -> Result<(Loaded, Vec<InternalCmd>), PatchError> {
+        let patch: Patch = text.parse().unwrap();
+        loaded.reload(&patch, &Registry::default(), keys, 1. / 44100.)
+    }
+
+    #[test]
+    fn test_reload() {
+        let mut keys = Keys::new();
+        let text = "mixer lead\nsynth lead sine";
+        let (loaded, cmds) = reload(&Loaded::new(), &mut keys, text).unwrap();
+        let lead = loaded.mixer("lead").unwrap();
+        match cmds[..] {
+            [InternalCmd::AddMixer(ROOT, key, _), InternalCmd::AddNamedSynth(mixer, _, _)] => {
+                assert_eq!(key, lead);
+                assert_eq!(mixer, lead);
+            }
+            _ => panic!("unexpected commands"),
+        }
+
+        // only the parameter
+        let text = "mixer lead\nsynth lead sine frequency=330";
+        let (loaded, cmds) = reload(&loaded, &mut keys, text).unwrap();
+        match cmds[..] {
+            [InternalCmd::SetNodeParameter(mixer, _, idx, _)] => {
+                assert_eq!(mixer, lead);
+                // amplitude, frequency
+                assert_eq!(idx, 1);
+            }
+            _ => panic!("unexpected commands"),
+        }
+
+        // nothing for an invalid patch
+        assert!(reload(&loaded, &mut keys, "mixer lead\nsynth lead nope").is_err());
+        // no room for one more mixer
+        let mut full = Keys::with_capacity(1);
+        assert_eq!(
+            reload(&loaded, &mut full, "mixer lead\nmixer bass").err(),
+            Some(PatchError::TooManyMixers)
+        );
+        assert_eq!(loaded.patch(), &text.parse::<Patch>().unwrap());
+
+        let (loaded, cmds) = reload(&loaded, &mut keys, "").unwrap();
+        assert!(loaded.mixer("lead").is_none());
+        match cmds[..] {
+            [InternalCmd::RemoveMixer(mixer)] => assert_eq!(mixer, lead),
+            _ => panic!("unexpected commands"),
+        }
+    }
+}
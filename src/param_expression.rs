@@ -1,55 +1,438 @@
-use nom::{double_s, alpha};
+use nom::{digit, Err, ErrorKind};
+use std::str::FromStr;
+use std::error;
+use std::fmt;
 use nom::IResult::*;
 
+use std::cell::{Cell, RefCell};
+
 use tree::pbus;
 use params::BusParam;
 
 /// simple calculator like expressions for param
 /// allow to write simple equations with "bus" params
-
 pub trait CalcParam: Send {
     fn calc(&self) -> f64;
-    fn connect(&mut self, buses: &mut pbus::BusSystem) {}
+    /// the value, without moving stateful functions forward ( `lag` .. )
+    fn peek(&self) -> f64 {
+        self.calc()
+    }
+    fn connect(&mut self, _buses: &mut pbus::BusSystem) {}
+    /// * `frame_t` : the frame time (in seconds), for time based functions
+    fn init(&mut self, _frame_t: f64) {}
+    /// the formula as written ( to save it in a patch )
+    fn source(&self) -> Option<&str> {
+        None
+    }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum CalcBinOp {
     Add,
     Sub,
     Mult,
+    Div,
+    Pow,
 }
 
-/// a node in the AST
-struct CalcNode {
-    left: Box<CalcParam>,
-    right: Box<CalcParam>,
-    op: CalcBinOp,
+impl CalcBinOp {
+    #[inline]
+    fn apply(&self, left: f64, right: f64) -> f64 {
+        match *self {
+            CalcBinOp::Add => left + right,
+            CalcBinOp::Sub => left - right,
+            CalcBinOp::Mult => left * right,
+            CalcBinOp::Div => left / right,
+            CalcBinOp::Pow => left.powf(right),
+        }
+    }
 }
 
+/// built-in functions
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CalcFn {
+    Sin,
+    Cos,
+    Exp,
+    Abs,
+    Min,
+    Max,
+    Clamp,
+    Mtof,
+    Dbtoa,
+    // stateful ones : calc() is expected once per frame
+    Lag,
+    Slew,
+    Smooth,
+}
 
-impl CalcParam for CalcNode {
-    fn calc(&self) -> f64 {
-        match &self.op {
-            CalcBinOp::Add => self.left.calc() + self.right.calc(),
-            CalcBinOp::Sub => self.left.calc() - self.right.calc(),
-            CalcBinOp::Mult => self.left.calc() * self.right.calc(),
+/// smooth(x) total time constant (s)
+const SMOOTH_TIME: f64 = 0.02;
+
+// one pole low pass, with a `time` constant in seconds
+fn one_pole(y: f64, x: f64, time: f64, frame_t: f64) -> f64 {
+    if time <= 0. {
+        return x;
+    }
+    y + (x - y) * (1. - (-frame_t / time).exp())
+}
+
+impl CalcFn {
+    fn from_name(name: &str) -> Option<CalcFn> {
+        match name {
+            "sin" => Some(CalcFn::Sin),
+            "cos" => Some(CalcFn::Cos),
+            "exp" => Some(CalcFn::Exp),
+            "abs" => Some(CalcFn::Abs),
+            "min" => Some(CalcFn::Min),
+            "max" => Some(CalcFn::Max),
+            "clamp" => Some(CalcFn::Clamp),
+            "mtof" => Some(CalcFn::Mtof),
+            "dbtoa" => Some(CalcFn::Dbtoa),
+            "lag" => Some(CalcFn::Lag),
+            "slew" => Some(CalcFn::Slew),
+            "smooth" => Some(CalcFn::Smooth),
+            _ => None,
         }
     }
 
-    fn connect(&mut self, buses: &mut pbus::BusSystem) {
-        self.left.connect(buses);
-        self.right.connect(buses);
+    fn arity(&self) -> usize {
+        match *self {
+            CalcFn::Min | CalcFn::Max | CalcFn::Lag | CalcFn::Slew => 2,
+            CalcFn::Clamp => 3,
+            _ => 1,
+        }
+    }
+
+    fn apply(&self, args: &[f64]) -> f64 {
+        match *self {
+            CalcFn::Sin => args[0].sin(),
+            CalcFn::Cos => args[0].cos(),
+            CalcFn::Exp => args[0].exp(),
+            CalcFn::Abs => args[0].abs(),
+            CalcFn::Min => args[0].min(args[1]),
+            CalcFn::Max => args[0].max(args[1]),
+            CalcFn::Clamp => args[0].max(args[1]).min(args[2]),
+            // midi note number to frequency (A4 = 69 = 440 Hz)
+            CalcFn::Mtof => 440. * 2f64.powf((args[0] - 69.) / 12.),
+            // decibels to amplitude
+            CalcFn::Dbtoa => 10f64.powf(args[0] / 20.),
+            // without state, a filter just follows its input
+            CalcFn::Lag | CalcFn::Slew | CalcFn::Smooth => args[0],
+        }
+    }
+
+    fn is_stateful(&self) -> bool {
+        matches!(*self, CalcFn::Lag | CalcFn::Slew | CalcFn::Smooth)
+    }
+
+    /// stateful functions :
+    /// * `lag(x, t)` follows x with a time constant of t seconds
+    /// * `slew(x, rate)` follows x by at most `rate` units per second
+    /// * `smooth(x)` a softer lag (two poles) of ~ 20 ms
+    ///
+    /// the output starts at the first input value (no fade in from 0).
+    fn filter(&self, args: &[f64], state: &Cell<(f64, f64)>, frame_t: f64) -> f64 {
+        let x = args[0];
+        let (y1, y2) = state.get();
+        if y1.is_nan() || frame_t == 0. {
+            state.set((x, x));
+            return x;
+        }
+
+        let (y1, y2) = match *self {
+            CalcFn::Lag => (one_pole(y1, x, args[1], frame_t), y2),
+            CalcFn::Slew => {
+                let max_step = args[1].abs() * frame_t;
+                (y1 + (x - y1).max(-max_step).min(max_step), y2)
+            }
+            CalcFn::Smooth => {
+                let y1 = one_pole(y1, x, SMOOTH_TIME / 2., frame_t);
+                (y1, one_pole(y2, y1, SMOOTH_TIME / 2., frame_t))
+            }
+            _ => return self.apply(args),
+        };
+        state.set((y1, y2));
+
+        match *self {
+            CalcFn::Smooth => y2,
+            _ => y1,
+        }
     }
 }
 
+/// the parser output (AST)
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Cst(f64),
+    Bus(String),
+    Neg(Box<Expr>),
+    BinOp(CalcBinOp, Box<Expr>, Box<Expr>),
+    Call(CalcFn, Vec<Expr>),
+}
+
+impl Expr {
+    /// constant folding : evaluates everything that doesn't depend on a bus
+    fn fold(self) -> Expr {
+        match self {
+            Expr::Neg(arg) => {
+                match arg.fold() {
+                    Expr::Cst(x) => Expr::Cst(-x),
+                    arg => Expr::Neg(Box::new(arg)),
+                }
+            }
+            Expr::BinOp(op, left, right) => {
+                match (left.fold(), right.fold()) {
+                    (Expr::Cst(l), Expr::Cst(r)) => Expr::Cst(op.apply(l, r)),
+                    (l, r) => Expr::BinOp(op, Box::new(l), Box::new(r)),
+                }
+            }
+            Expr::Call(func, args) => {
+                let args: Vec<Expr> = args.into_iter().map(Expr::fold).collect();
+                let mut values = [0.; 3];
+                for (v, arg) in values.iter_mut().zip(args.iter()) {
+                    match *arg {
+                        Expr::Cst(x) => *v = x,
+                        _ => return Expr::Call(func, args),
+                    }
+                }
+                Expr::Cst(func.apply(&values))
+            }
+            expr => expr,
+        }
+    }
+
+    /// appends the postfix code of the expression. returns the stack depth
+    /// it needs (not counting the accumulator).
+    fn compile(&self, code: &mut Vec<Instr>, buses: &mut Vec<String>) -> usize {
+        match *self {
+            Expr::Cst(x) => {
+                code.push(Instr::Cst(x));
+                0
+            }
+            Expr::Bus(ref name) => {
+                code.push(Instr::Bus(bus_index(name, buses)));
+                0
+            }
+            Expr::Neg(ref arg) => {
+                let depth = arg.compile(code, buses);
+                code.push(Instr::Neg);
+                depth
+            }
+            // a constant or a bus on the right side doesn't need the stack
+            Expr::BinOp(op, ref left, ref right) => {
+                let left_depth = left.compile(code, buses);
+                match **right {
+                    Expr::Cst(x) => {
+                        code.push(Instr::BinOpCst(op, x));
+                        left_depth
+                    }
+                    Expr::Bus(ref name) => {
+                        code.push(Instr::BinOpBus(op, bus_index(name, buses)));
+                        left_depth
+                    }
+                    _ => {
+                        code.push(Instr::Push);
+                        let right_depth = right.compile(code, buses);
+                        code.push(Instr::BinOp(op));
+                        left_depth.max(right_depth + 1)
+                    }
+                }
+            }
+            Expr::Call(func, ref args) => {
+                let mut depth = 0;
+                for (idx, arg) in args.iter().enumerate() {
+                    if idx > 0 {
+                        code.push(Instr::Push);
+                    }
+                    depth = depth.max(arg.compile(code, buses) + idx);
+                }
+                if func.is_stateful() {
+                    code.push(Instr::Filter(func));
+                } else {
+                    code.push(Instr::Call(func));
+                }
+                depth
+            }
+        }
+    }
+}
+
+// one reader per bus, even if it is used several times
+fn bus_index(name: &str, buses: &mut Vec<String>) -> usize {
+    match buses.iter().position(|b| b == name) {
+        Some(idx) => idx,
+        None => {
+            buses.push(name.to_string());
+            buses.len() - 1
+        }
+    }
+}
 
-/// a literal constant
-struct CalcCst {
-    val: f64,
+/// instructions for the stack machine. The top of the stack is kept aside
+/// in an accumulator.
+#[derive(Debug, PartialEq)]
+enum Instr {
+    /// load a constant in the accumulator
+    Cst(f64),
+    /// load a bus value in the accumulator
+    Bus(usize),
+    /// push the accumulator on the stack
+    Push,
+    Neg,
+    /// acc = pop() op acc
+    BinOp(CalcBinOp),
+    /// acc = acc op constant
+    BinOpCst(CalcBinOp, f64),
+    /// acc = acc op bus
+    BinOpBus(CalcBinOp, usize),
+    /// the last argument is in the accumulator, the others on the stack.
+    Call(CalcFn),
+    /// same as Call, with the next filter state.
+    Filter(CalcFn),
 }
 
-impl CalcParam for CalcCst {
+/// most formulas are that simple ("freq * 1.1"): skip the interpreter loop
+#[derive(Debug, PartialEq)]
+enum Shortcut {
+    Cst(f64),
+    Bus(usize),
+    BusOpCst(usize, CalcBinOp, f64),
+    None,
+}
+
+impl Shortcut {
+    fn new(code: &[Instr]) -> Shortcut {
+        match (code.len(), code.first(), code.get(1)) {
+            (1, Some(&Instr::Cst(x)), _) => Shortcut::Cst(x),
+            (1, Some(&Instr::Bus(idx)), _) => Shortcut::Bus(idx),
+            (2, Some(&Instr::Bus(idx)), Some(&Instr::BinOpCst(op, x))) => {
+                Shortcut::BusOpCst(idx, op, x)
+            }
+            _ => Shortcut::None,
+        }
+    }
+}
+
+/// A compiled expression : a flat postfix program evaluated on a stack
+/// allocated once and for all (no allocation, no virtual call per node)
+struct CalcProgram {
+    code: Vec<Instr>,
+    shortcut: Shortcut,
+    buses: Vec<BusParam>,
+    stack_size: usize,
+    stack: RefCell<Vec<f64>>,
+    /// one per Filter instruction, in order
+    states: Vec<Cell<(f64, f64)>>,
+    /// the states put back after `peek`
+    saved: RefCell<Vec<(f64, f64)>>,
+    frame_t: f64,
+    source: String,
+}
+
+impl CalcProgram {
+    fn new(expr: Expr) -> CalcProgram {
+        let mut code = Vec::new();
+        let mut buses = Vec::new();
+        let depth = expr.fold().compile(&mut code, &mut buses);
+        let nb_filters = code.iter()
+            .filter(|instr| matches!(**instr, Instr::Filter(_)))
+            .count();
+        CalcProgram {
+            shortcut: Shortcut::new(&code),
+            code,
+            buses: buses.into_iter().map(BusParam::NotConnected).collect(),
+            stack_size: depth,
+            stack: RefCell::new(vec![0.; depth]),
+            // NaN : no value yet
+            states: (0..nb_filters).map(|_| Cell::new((f64::NAN, f64::NAN))).collect(),
+            saved: RefCell::new(vec![(0., 0.); nb_filters]),
+            frame_t: 0.,
+            source: String::new(),
+        }
+    }
+
+    #[inline]
+    fn run(&self, stack: &mut [f64]) -> f64 {
+        let mut acc = 0.;
+        let mut top = 0; // stack pointer (next free slot)
+        let mut states = self.states.iter();
+
+        for instr in self.code.iter() {
+            match *instr {
+                Instr::Cst(x) => acc = x,
+                Instr::Bus(idx) => acc = self.buses[idx].value(),
+                Instr::Push => {
+                    stack[top] = acc;
+                    top += 1;
+                }
+                Instr::Neg => acc = -acc,
+                Instr::BinOp(op) => {
+                    top -= 1;
+                    acc = op.apply(stack[top], acc);
+                }
+                Instr::BinOpCst(op, x) => acc = op.apply(acc, x),
+                Instr::BinOpBus(op, idx) => acc = op.apply(acc, self.buses[idx].value()),
+                Instr::Call(func) |
+                Instr::Filter(func) => {
+                    let mut args = [0.; 3];
+                    let arity = func.arity();
+                    top -= arity - 1;
+                    args[..arity - 1].copy_from_slice(&stack[top..top + arity - 1]);
+                    args[arity - 1] = acc;
+                    acc = match *instr {
+                        Instr::Filter(_) => func.filter(&args, states.next().unwrap(), self.frame_t),
+                        _ => func.apply(&args),
+                    };
+                }
+            }
+        }
+        acc
+    }
+}
+
+impl CalcParam for CalcProgram {
     fn calc(&self) -> f64 {
-        self.val
+        match self.shortcut {
+            Shortcut::Cst(x) => return x,
+            Shortcut::Bus(idx) => return self.buses[idx].value(),
+            Shortcut::BusOpCst(idx, op, x) => return op.apply(self.buses[idx].value(), x),
+            Shortcut::None => {}
+        }
+        // linear chains like "a * 2 + b" don't need the stack either
+        if self.stack_size == 0 {
+            return self.run(&mut []);
+        }
+        self.run(&mut self.stack.borrow_mut())
+    }
+
+    fn peek(&self) -> f64 {
+        if self.states.is_empty() {
+            return self.calc();
+        }
+        let mut saved = self.saved.borrow_mut();
+        for (state, cell) in saved.iter_mut().zip(self.states.iter()) {
+            *state = cell.get();
+        }
+        let value = self.calc();
+        for (cell, &state) in self.states.iter().zip(saved.iter()) {
+            cell.set(state);
+        }
+        value
+    }
+
+    fn connect(&mut self, buses: &mut pbus::BusSystem) {
+        for bus in self.buses.iter_mut() {
+            bus.connect_to_bus(buses);
+        }
+    }
+
+    fn init(&mut self, frame_t: f64) {
+        self.frame_t = frame_t;
+    }
+
+    fn source(&self) -> Option<&str> {
+        Some(&self.source)
     }
 }
 
@@ -61,66 +444,329 @@ impl CalcParam for BusParam {
     fn connect(&mut self, buses: &mut pbus::BusSystem) {
         self.connect_to_bus(buses)
     }
+
+    fn source(&self) -> Option<&str> {
+        Some(self.name())
+    }
 }
 
 // parsing shit. largely inspired from the simple calculator nom example
 
-fn parse_constant(value: f64) -> Box<CalcParam> {
-    Box::new(CalcCst { val: value })
+fn parse_constant(value: f64) -> Expr {
+    Expr::Cst(value)
+}
+
+fn parse_bus_param(name: &str) -> Expr {
+    Expr::Bus(name.to_string())
+}
+
+fn parse_neg(arg: Expr) -> Expr {
+    Expr::Neg(Box::new(arg))
 }
 
-fn parse_bus_param(name: &str) -> Box<CalcParam> {
-    Box::new(BusParam::NotConnected(name.to_string()))
+fn parse_call(func: CalcFn, first: Expr, rem: Vec<Expr>) -> Option<Expr> {
+    let mut args = rem;
+    args.insert(0, first);
+    if func.arity() == args.len() {
+        Some(Expr::Call(func, args))
+    } else {
+        None
+    }
 }
 
+// error codes. return_error! stops backtracking once we know what we're
+// parsing (say after an operator), so that errors are reported where they
+// actually are.
+const ERR_OPERAND: u32 = 1;
+const ERR_CLOSING_PAREN: u32 = 2;
+const ERR_UNKNOWN_FUNCTION: u32 = 3;
+const ERR_ARGUMENT_COUNT: u32 = 4;
+
 // terminals
-named!( constant_p<&str, Box<CalcParam>>, map!(ws!(double_s), parse_constant ));
-named!( variable_p<&str, Box<CalcParam>>, map!(ws!(alpha), parse_bus_param));
+// numbers : 2, 2., 2.5, .5, 1e-3 ..
+named!( number_p<&str, f64>, map_res!(
+    recognize!(tuple!(
+        alt!(
+            recognize!(tuple!(digit, opt!(preceded!(char!('.'), opt!(digit))))) |
+            recognize!(preceded!(char!('.'), digit))
+        ),
+        opt!(tuple!(one_of!("eE"), opt!(one_of!("+-")), digit))
+    )),
+    FromStr::from_str
+));
+
+named!( constant_p<&str, Expr>, map!(ws!(number_p), parse_constant ));
+
+// bus names : "freq", "lfo_2", "voice1.cutoff" or OSC like "/voice/1/freq".
+// a '/' inside a name is only allowed when the name starts with one,
+// otherwise "a/b" is a division. ( "/a/b / 2" to divide an OSC bus )
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+named!( name_segment_p<&str, &str>, recognize!(pair!(
+    take_while1!(is_name_start),
+    take_while!(is_name_char)
+)));
+
+named!( identifier_p<&str, &str>, alt!(
+    recognize!(many1!(preceded!(char!('/'), take_while1!(is_name_char)))) |
+    name_segment_p
+));
 
-named!( parens_p<&str, Box<CalcParam>>, ws!(delimited!(char!('('), expression_p, char!(')'))));
+named!( variable_p<&str, Expr>, map!(ws!(identifier_p), parse_bus_param));
+
+named!(function_p<&str, CalcFn>, map_opt!(ws!(identifier_p), CalcFn::from_name));
+
+// function call. once we've seen "name(", it has to be a function call.
+named!(call_p<&str, Expr>, do_parse!(
+    func: return_error!(ErrorKind::Custom(ERR_UNKNOWN_FUNCTION), function_p) >>
+    char!('(') >>
+    first: return_error!(ErrorKind::Custom(ERR_OPERAND), expression_p) >>
+    rem: many0!(preceded!(
+        ws!(char!(',')),
+        return_error!(ErrorKind::Custom(ERR_OPERAND), expression_p)
+    )) >>
+    return_error!(ErrorKind::Custom(ERR_CLOSING_PAREN), ws!(char!(')'))) >>
+    call: expr_opt!(parse_call(func, first, rem)) >>
+    (call)
+));
 
 // terminated leme
-named!(op_p<&str, Box<CalcParam>>, alt!( constant_p | variable_p | parens_p));
+// (return_error! so that call and parens errors are not swallowed by alt!)
+named!(op_p<&str, Expr>, alt!(
+    constant_p |
+    do_parse!(
+        peek!(tuple!(ws!(identifier_p), char!('('))) >>
+        call: return_error!(ErrorKind::Custom(ERR_ARGUMENT_COUNT), call_p) >>
+        (call)
+    ) |
+    variable_p |
+    do_parse!(
+        ws!(char!('(')) >>
+        expr: return_error!(ErrorKind::Custom(ERR_OPERAND), expression_p) >>
+        return_error!(ErrorKind::Custom(ERR_CLOSING_PAREN), ws!(char!(')'))) >>
+        (expr)
+    )
+));
 
 // achtung with op precedence ..
-named!(factor_p<&str, Box<CalcParam> >, do_parse!(
+// exponentiation binds tighter than unary minus ( -x^2 is -(x^2) ), and is
+// right associative : the exponent (unary_p) eats up the rest of the chain.
+named!(power_p<&str, Expr>, do_parse!(
     op: op_p >>
-    rem: many0!(tuple!(char!('*'), factor_p)) >>
+    rem: many0!(tuple!(
+        ws!(char!('^')),
+        return_error!(ErrorKind::Custom(ERR_OPERAND), unary_p)
+    )) >>
+    (parse_exp(op, rem))
+));
+
+named!(unary_p<&str, Expr>, do_parse!(
+    neg: many0!(ws!(char!('-'))) >>
+    op: power_p >>
+    (if neg.len() % 2 == 1 { parse_neg(op) } else { op })
+));
+
+named!(factor_p<&str, Expr>, do_parse!(
+    op: unary_p >>
+    rem: many0!(tuple!(
+        ws!(alt!(char!('*') | char!('/'))),
+        return_error!(ErrorKind::Custom(ERR_OPERAND), unary_p)
+    )) >>
     (parse_exp(op, rem))
 ));
 
-named!(expression_p<&str, Box<CalcParam> >, do_parse!(
+named!(expression_p<&str, Expr>, do_parse!(
     f: factor_p >>
-    rem: many0!(tuple!( alt!(char!('+') | char!('-')), factor_p)) >>
+    rem: many0!(tuple!(
+        ws!(alt!(char!('+') | char!('-'))),
+        return_error!(ErrorKind::Custom(ERR_OPERAND), factor_p)
+    )) >>
     (parse_exp(f, rem))
 ));
 
-fn parse_exp(left: Box<CalcParam>, rem: Vec<(char, Box<CalcParam>)>) -> Box<CalcParam> {
-    rem.into_iter().fold(left, |acc, (op, expr)| match op {
-        '*' => Box::new(CalcNode {
-            left: acc,
-            right: expr,
-            op: CalcBinOp::Mult,
-        }),
-        '-' => Box::new(CalcNode {
-            left: acc,
-            right: expr,
-            op: CalcBinOp::Sub,
-        }),
-        '+' => Box::new(CalcNode {
-            left: acc,
-            right: expr,
-            op: CalcBinOp::Add,
-        }),
-        _ => panic!(format!("unknown operation : {}", op)),
+fn parse_exp(left: Expr, rem: Vec<(char, Expr)>) -> Expr {
+    rem.into_iter().fold(left, |acc, (op, expr)| {
+        let op = match op {
+            '*' => CalcBinOp::Mult,
+            '/' => CalcBinOp::Div,
+            '^' => CalcBinOp::Pow,
+            '-' => CalcBinOp::Sub,
+            '+' => CalcBinOp::Add,
+            _ => panic!("unknown operation : {}", op),
+        };
+        Expr::BinOp(op, Box::new(acc), Box::new(expr))
     })
 }
 
+/// What the parser was expecting when it failed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Expected {
+    /// a number, a bus name, a function call or a parenthesized expression
+    Operand,
+    /// closing parenthesis
+    ClosingParen,
+    /// a built-in function name
+    Function,
+    /// the built-in function arguments (wrong argument count)
+    Arguments,
+    /// an operator, or the end of the expression
+    OperatorOrEnd,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let what = match *self {
+            Expected::Operand => "a number, a bus name, a function call or '('",
+            Expected::ClosingParen => "')'",
+            Expected::Function => "a function name",
+            Expected::Arguments => "a different number of arguments",
+            Expected::OperatorOrEnd => "an operator or the end of the expression",
+        };
+        fmt.write_str(what)
+    }
+}
+
+/// Error from `parse_param_expression`
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    /// the whole expression
+    pub expression: String,
+    /// byte offset in the expression where the error is
+    pub position: usize,
+    pub expected: Expected,
+}
+
+impl ParseError {
+    /// the offending input ( the remaining of the expression from the error )
+    pub fn found(&self) -> &str {
+        &self.expression[self.position..]
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let found = if self.found().is_empty() {
+            "the end of the expression".to_string()
+        } else {
+            format!("\"{}\"", self.found())
+        };
+        write!(
+            fmt,
+            "expected {} at position {}, found {} (in \"{}\")",
+            self.expected,
+            self.position,
+            found,
+            self.expression
+        )
+    }
+}
+
+impl error::Error for ParseError {
+    fn description(&self) -> &str {
+        "invalid parameter expression"
+    }
+}
+
+// the innermost error with one of our codes
+fn innermost_error<'a>(err: &Err<&'a str>) -> Option<(u32, &'a str)> {
+    match *err {
+        Err::NodePosition(ErrorKind::Custom(code), pos, ref next) => {
+            next.iter().filter_map(innermost_error).next().or(
+                Some((code, pos)),
+            )
+        }
+        Err::Position(ErrorKind::Custom(code), pos) => Some((code, pos)),
+        Err::Node(_, ref next) |
+        Err::NodePosition(_, _, ref next) => next.iter().filter_map(innermost_error).next(),
+        _ => None,
+    }
+}
+
+fn parse_error(input: &str, err: &Err<&str>) -> ParseError {
+    let (expected, rem) = match innermost_error(err) {
+        Some((code, rem)) => {
+            let expected = match code {
+                ERR_CLOSING_PAREN => Expected::ClosingParen,
+                ERR_UNKNOWN_FUNCTION => Expected::Function,
+                ERR_ARGUMENT_COUNT => Expected::Arguments,
+                _ => Expected::Operand,
+            };
+            (expected, rem)
+        }
+        None => {
+            match *err {
+                // the terminal ';' : some leftover in the expression
+                Err::Position(ErrorKind::Char, rem) => (Expected::OperatorOrEnd, rem),
+                Err::Position(_, rem) |
+                Err::NodePosition(_, rem, _) => (Expected::Operand, rem),
+                _ => (Expected::Operand, ";"),
+            }
+        }
+    };
+
+    // rem is a suffix of the ';' terminated input. skip leading spaces.
+    let rem = rem.trim_start();
+    let position = (input.len() + 1).saturating_sub(rem.len()).min(input.len());
+    ParseError {
+        expression: input.to_string(),
+        position,
+        expected,
+    }
+}
+
+// nom parsers work on streams : the end of the expression is marked with
+// a ';' so that they know when to stop waiting for more input.
+named!(terminated_expression_p<&str, Expr>, terminated!(expression_p, char!(';')));
+
+named!(terminated_identifier_p<&str, &str>, terminated!(identifier_p, char!(';')));
+
+/// is `name` a bus name that can be used in expressions ?
+///
+/// bus names start with a letter or '_', followed by letters, digits, '_'
+/// or '.' ( `lfo_2`, `voice1.cutoff` ). OSC like addresses are also
+/// accepted : `/voice/1/freq`. Note that in expressions, such an address
+/// needs spaces around a following division ( `/voice/1/freq / 2` ).
+pub fn is_bus_name(name: &str) -> bool {
+    matches!(terminated_identifier_p(&format!("{};", name)), Done("", _))
+}
+
 /// parse a string as useable CalcParam.
-pub fn parse_param_expression(input: &str) -> Result<Box<CalcParam>, String> {
-    match expression_p(input) {
-        Done(_, expr) => Ok(expr),
-        _ => Err("shit".to_string()),
+///
+/// expressions are made of numbers, bus names ( see `is_bus_name` ), parens,
+/// the `+ - * / ^` operators, unary minus, and the built-in functions :
+/// `sin(x)`, `cos(x)`, `exp(x)`, `abs(x)`, `min(a, b)`, `max(a, b)`,
+/// `clamp(x, lo, hi)`, `mtof(note)` (midi note to Hz) and `dbtoa(db)`
+/// (decibels to amplitude). `^` binds tighter than unary minus and is right
+/// associative.
+///
+/// `lag(x, t)`, `slew(x, rate)` and `smooth(x)` filter `x` over time : they
+/// keep a state and move forward once per `calc` ( see `CalcFn::filter` ).
+///
+/// the whole input has to be an expression ( `2 * x )` is refused ). the
+/// error tells where it failed ( `position`, a byte offset ), what was
+/// `expected` there and what was `found`.
+pub fn parse_param_expression(input: &str) -> Result<Box<dyn CalcParam>, ParseError> {
+    let terminated = format!("{};", input);
+    match terminated_expression_p(&terminated) {
+        Done("", expr) => {
+            let mut program = CalcProgram::new(expr);
+            program.source = input.trim().to_string();
+            Ok(Box::new(program))
+        }
+        Done(rem, _) => Err(parse_error(input, &Err::Position(ErrorKind::Char, rem))),
+        Error(err) => Err(parse_error(input, &err)),
+        Incomplete(_) => {
+            Err(parse_error(
+                input,
+                &Err::Position(ErrorKind::Custom(ERR_OPERAND), ""),
+            ))
+        }
     }
 }
 
@@ -137,4 +783,323 @@ mod tests {
 
 
     }
+
+    fn calc(expr: &str) -> f64 {
+        parse_param_expression(expr).unwrap().calc()
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(2.5, calc("10 / 4"));
+        assert_eq!(2.0, calc("10 / 4 / 1.25"));
+        assert_eq!(-3.0, calc("-3"));
+        assert_eq!(-1.0, calc("2 - 3"));
+        assert_eq!(5.0, calc("2 - -3"));
+        assert_eq!(-6.0, calc("-(1 + 2) * 2"));
+        assert_eq!(7.0, calc("1 + 2 * 3"));
+        assert_eq!(-4.0, calc("-2 ^ 2"));
+        assert_eq!(512.0, calc("2 ^ 3 ^ 2"));
+        assert_eq!(0.25, calc("2 ^ -2"));
+        assert_eq!(18.0, calc("2 * 3 ^ 2"));
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_eq!(0.0, calc("sin(0)"));
+        assert_eq!(1.0, calc("exp(0)"));
+        assert_eq!(2.0, calc("min(2, 3)"));
+        assert_eq!(3.0, calc("max(2, 3)"));
+        assert_eq!(1.0, calc("clamp(4, -1, 1)"));
+        assert_eq!(440.0, calc("mtof(69)"));
+        assert_eq!(880.0, calc("mtof(60 + 9) * 2"));
+        assert_eq!(10.0, calc("dbtoa(20)"));
+        assert_eq!(-1.0, calc("-abs(min(-1, 3))"));
+
+    }
+
+    fn error(expr: &str) -> (usize, Expected) {
+        let err = parse_param_expression(expr).err().expect(expr);
+        (err.position, err.expected)
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!((6, Expected::OperatorOrEnd), error("2 * x )"));
+        assert_eq!((2, Expected::OperatorOrEnd), error("2 3"));
+        assert_eq!((4, Expected::Operand), error("2 * )"));
+        assert_eq!((3, Expected::Operand), error("2 +"));
+        assert_eq!((0, Expected::Operand), error(""));
+        assert_eq!((6, Expected::ClosingParen), error("(1 + 2"));
+        assert_eq!((9, Expected::Operand), error("2 * (1 + )"));
+        assert_eq!((0, Expected::Function), error("foo(2)"));
+        assert_eq!((4, Expected::Function), error("1 + bar(2)"));
+        assert_eq!((0, Expected::Arguments), error("clamp(1, 2)"));
+        assert_eq!((7, Expected::Operand), error("min(1, )"));
+        assert_eq!((9, Expected::ClosingParen), error("max(1, 2 3)"));
+
+        let err = parse_param_expression("(1 + 2) x").err().unwrap();
+        assert_eq!(err.found(), "x");
+        assert_eq!(
+            format!("{}", err),
+            "expected an operator or the end of the expression at position 8, \
+             found \"x\" (in \"(1 + 2) x\")"
+        );
+    }
+
+    #[test]
+    fn test_bus_names() {
+        assert!(is_bus_name("freq"));
+        assert!(is_bus_name("lfo_2"));
+        assert!(is_bus_name("voice1.cutoff"));
+        assert!(is_bus_name("/synth/freq"));
+        assert!(is_bus_name("/voice/1/freq"));
+        assert!(is_bus_name("_x"));
+        assert!(!is_bus_name("2x"));
+        assert!(!is_bus_name("a/b"));
+        assert!(!is_bus_name("/synth/"));
+        assert!(!is_bus_name("x y"));
+        assert!(!is_bus_name(""));
+
+        let mut bus = pbus::BusSystem::new();
+        let mut e = parse_param_expression("voice1.cutoff * 2 + /synth/freq / lfo_2").unwrap();
+        e.connect(&mut bus);
+        bus.publish("voice1.cutoff", 100.).unwrap();
+        bus.publish("/synth/freq", 10.).unwrap();
+        bus.publish("lfo_2", 5.).unwrap();
+        assert_eq!(202.0, e.calc());
+    }
+
+    fn program(expr: &str) -> CalcProgram {
+        match terminated_expression_p(&format!("{};", expr)) {
+            Done(_, e) => CalcProgram::new(e),
+            _ => panic!("can't parse {}", expr),
+        }
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        assert_eq!(program("440 * 2 ^ (3 / 12) + 1 - 1").code.len(), 1);
+        assert_eq!(program("-mtof(69) * dbtoa(0)").code, vec![Instr::Cst(-440.)]);
+        assert_eq!(
+            program("freq * (2 + 3)").code,
+            vec![Instr::Bus(0), Instr::BinOpCst(CalcBinOp::Mult, 5.)]
+        );
+        assert_eq!(
+            program("freq * (2 + 3)").shortcut,
+            Shortcut::BusOpCst(0, CalcBinOp::Mult, 5.)
+        );
+        assert_eq!(program("x + (2 * x)").shortcut, Shortcut::None);
+        // only the constant part of a function call
+        assert_eq!(
+            program("max(x, 2 * 2)").code,
+            vec![Instr::Bus(0), Instr::Push, Instr::Cst(4.), Instr::Call(CalcFn::Max)]
+        );
+    }
+
+    #[test]
+    fn test_compiled_program() {
+        // a single reader per bus
+        let p = program("x * x + y / x");
+        assert_eq!(p.buses.len(), 2);
+
+        // stack size is what the expression needs
+        assert_eq!(program("1 + x").stack.borrow().len(), 0);
+        assert_eq!(program("x + (x + (x + x))").stack.borrow().len(), 2);
+        assert_eq!(program("x + (x + (x + -x))").stack.borrow().len(), 3);
+        assert_eq!(program("((-x + x) + x) + x").stack.borrow().len(), 0);
+        assert_eq!(program("clamp(x, y, -y)").stack.borrow().len(), 2);
+
+        let mut bus = pbus::BusSystem::new();
+        let mut e = parse_param_expression("clamp(x * 2, y, x + y) - -x").unwrap();
+        e.connect(&mut bus);
+        bus.publish("x", 3.).unwrap();
+        bus.publish("y", 1.).unwrap();
+        assert_eq!(7.0, e.calc());
+        bus.publish("y", 7.).unwrap();
+        assert_eq!(10.0, e.calc());
+    }
+
+    #[test]
+    fn test_functions_with_bus() {
+        let mut bus = pbus::BusSystem::new();
+        let mut e = parse_param_expression("mtof(note) / ratio").unwrap();
+        e.connect(&mut bus);
+        bus.publish("note", 57.).unwrap();
+        bus.publish("ratio", 2.).unwrap();
+        assert_eq!(110.0, e.calc());
+    }
+
+    #[test]
+    fn test_stateful_functions() {
+        let mut bus = pbus::BusSystem::new();
+        let mut slew = parse_param_expression("slew(x, 100)").unwrap();
+        let mut lag = parse_param_expression("lag(x, 0.01) * 2").unwrap();
+        let mut smooth = parse_param_expression("smooth(x)").unwrap();
+        for e in [&mut slew, &mut lag, &mut smooth] {
+            e.connect(&mut bus);
+            e.init(0.001);
+        }
+
+        // starts at the first value
+        bus.publish("x", 1.).unwrap();
+        assert_eq!(1., slew.calc());
+        assert_eq!(2., lag.calc());
+        assert_eq!(1., smooth.calc());
+
+        bus.publish("x", 2.).unwrap();
+        // at most 100 / s
+        assert!((slew.calc() - 1.1).abs() < 1e-9);
+        assert!((slew.calc() - 1.2).abs() < 1e-9);
+        // 1 - exp(-0.1) of the way
+        let expected = 2. * (2. - (-0.1f64).exp());
+        assert!((lag.calc() - expected).abs() < 1e-9);
+        // two poles start slower than one
+        let first = smooth.calc();
+        assert!(first > 1. && first < 1.01);
+
+        for _ in 0..200 {
+            slew.calc();
+            lag.calc();
+            smooth.calc();
+        }
+        assert_eq!(2., slew.calc());
+        assert!((lag.calc() - 4.).abs() < 1e-6);
+        assert!((smooth.calc() - 2.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_stateful_functions_not_initialized() {
+        // no frame time : just follows the input
+        let mut bus = pbus::BusSystem::new();
+        let mut e = parse_param_expression("lag(x, 1) + 1").unwrap();
+        e.connect(&mut bus);
+        bus.publish("x", 1.).unwrap();
+        assert_eq!(2., e.calc());
+        bus.publish("x", 3.).unwrap();
+        assert_eq!(4., e.calc());
+
+        // not folded into a Call
+        assert_eq!(
+            program("smooth(x)").code,
+            vec![Instr::Bus(0), Instr::Filter(CalcFn::Smooth)]
+        );
+        assert_eq!(program("smooth(x) * smooth(y)").states.len(), 2);
+    }
+}
+
+// benchmarks of the compiled formulas against a tree walking evaluator
+// ( as they were evaluated before `CalcProgram` ). they run on stable :
+//
+//   cargo test --release --lib param_expression::benches -- --ignored --nocapture
+//
+// ns per frame, on a Xeon (release build) :
+//
+//   formula                                   tree walking  compiled
+//   freq * 1.1                                     16          4.7
+//   440 * 2 ^ (3 / 12) + 1 - 1                     55          2.7
+//   mtof(note + 12 * 2) * (1 + 0.5 * 2)
+//       / freq - -freq * 2 ^ 0.5                  100           35
+#[cfg(test)]
+mod benches {
+    use super::*;
+    use std::hint::black_box;
+    use std::time::Instant;
+
+    // one boxed node per AST node, evaluated recursively, no constant folding
+    enum Walked {
+        Cst(f64),
+        Bus(BusParam),
+        Neg(Box<dyn CalcParam>),
+        BinOp(CalcBinOp, Box<dyn CalcParam>, Box<dyn CalcParam>),
+        Call(CalcFn, Vec<Box<dyn CalcParam>>),
+    }
+
+    impl CalcParam for Walked {
+        fn calc(&self) -> f64 {
+            match *self {
+                Walked::Cst(x) => x,
+                Walked::Bus(ref bus) => bus.value(),
+                Walked::Neg(ref arg) => -arg.calc(),
+                Walked::BinOp(op, ref left, ref right) => op.apply(left.calc(), right.calc()),
+                Walked::Call(func, ref args) => {
+                    let mut values = [0.; 3];
+                    for (v, arg) in values.iter_mut().zip(args.iter()) {
+                        *v = arg.calc();
+                    }
+                    func.apply(&values)
+                }
+            }
+        }
+
+        fn connect(&mut self, buses: &mut pbus::BusSystem) {
+            match *self {
+                Walked::Bus(ref mut bus) => bus.connect_to_bus(buses),
+                Walked::Neg(ref mut arg) => arg.connect(buses),
+                Walked::BinOp(_, ref mut left, ref mut right) => {
+                    left.connect(buses);
+                    right.connect(buses);
+                }
+                Walked::Call(_, ref mut args) => {
+                    for arg in args.iter_mut() {
+                        arg.connect(buses);
+                    }
+                }
+                Walked::Cst(_) => {}
+            }
+        }
+    }
+
+    fn walked(expr: Expr) -> Box<dyn CalcParam> {
+        Box::new(match expr {
+            Expr::Cst(x) => Walked::Cst(x),
+            Expr::Bus(name) => Walked::Bus(BusParam::NotConnected(name)),
+            Expr::Neg(arg) => Walked::Neg(walked(*arg)),
+            Expr::BinOp(op, left, right) => Walked::BinOp(op, walked(*left), walked(*right)),
+            Expr::Call(func, args) => Walked::Call(func, args.into_iter().map(walked).collect()),
+        })
+    }
+
+    // ns per frame, over periods of 1024 frames
+    fn time(mut e: Box<dyn CalcParam>) -> f64 {
+        let mut bus = pbus::BusSystem::new();
+        e.connect(&mut bus);
+        // (not all the expressions use all the buses)
+        bus.publish("freq", 220.).ok();
+        bus.publish("note", 60.).ok();
+
+        const PERIODS: u32 = 2000;
+        let start = Instant::now();
+        for _ in 0..PERIODS {
+            let mut acc = 0.;
+            for _ in 0..1024 {
+                acc += e.calc();
+            }
+            black_box(acc);
+        }
+        start.elapsed().as_secs_f64() * 1e9 / (PERIODS as f64 * 1024.)
+    }
+
+    fn bench_expression(expr: &str) {
+        let tree = match terminated_expression_p(&format!("{};", expr)) {
+            Done("", expr) => walked(expr),
+            _ => panic!("can't parse {}", expr),
+        };
+        let walking = time(tree);
+        let compiled = time(parse_param_expression(expr).unwrap());
+        println!(
+            "{:<70} tree walking {:6.2} ns, compiled {:6.2} ns ( x{:.1} )",
+            expr,
+            walking,
+            compiled,
+            walking / compiled
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn benchmark_formulas() {
+        bench_expression("freq * 1.1");
+        bench_expression("440 * 2 ^ (3 / 12) + 1 - 1");
+        bench_expression("mtof(note + 12 * 2) * (1 + 0.5 * 2) / freq - -freq * 2 ^ 0.5");
+    }
 }
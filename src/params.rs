@@ -1,8 +1,13 @@
 
 use tree::pbus;
-use param_expression::CalcParam;
+use param_expression::{CalcParam, ParseError, is_bus_name, parse_param_expression};
 use std::fmt;
+use std::error;
+use std::str::FromStr;
 use std::cmp;
+use std::ptr;
+use std::cell::Cell;
+use std::mem;
 
 impl<T> fmt::Debug for pbus::Reader<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -20,12 +25,20 @@ impl<T> cmp::PartialEq for pbus::Reader<T> {
 #[derive(Debug, PartialEq)]
 pub enum BusParam {
     NotConnected(String),
-    Connected(pbus::Reader<f64>),
+    /// (the name is kept to save the parameter in a patch)
+    Connected(String, pbus::Reader<f64>),
 }
 
 impl BusParam {
+    pub fn name(&self) -> &str {
+        match *self {
+            BusParam::NotConnected(ref name) |
+            BusParam::Connected(ref name, _) => name,
+        }
+    }
+
     pub fn value(&self) -> f64 {
-        if let BusParam::Connected(ref rcvr) = *self {
+        if let BusParam::Connected(_, ref rcvr) = *self {
             rcvr.value()
         } else {
             panic!("un-connected bus");
@@ -33,20 +46,92 @@ impl BusParam {
     }
 
     pub fn connect_to_bus(&mut self, buses: &mut pbus::BusSystem) {
-        let recvr = {
-            if let BusParam::NotConnected(ref busid) = *self {
-                Some(buses.sub(&busid))
-            } else {
-                None
-            }
+        if let BusParam::NotConnected(ref mut busid) = *self {
+            let recvr = buses.sub(busid);
+            let name = mem::take(busid);
+            *self = BusParam::Connected(name, recvr);
+        }
+    }
+}
+
+
+/// how a smoothed bus value goes to a new value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ramp {
+    /// constant speed, the new value is reached after `time`
+    Linear,
+    /// one pole filter, `time` is the time constant
+    Exponential,
+}
+
+/// smoothing state of a bus parameter
+pub struct Smoothing {
+    ramp: Ramp,
+    time: f64,
+    frame_t: f64,
+    coef: f64,
+    // current, target, step (per frame)
+    state: Cell<(f64, f64, f64)>,
+}
+
+impl Smoothing {
+    pub fn new(ramp: Ramp, time: f64) -> Smoothing {
+        Smoothing {
+            ramp,
+            time,
+            frame_t: 0.,
+            coef: 1.,
+            // NaN : no value yet
+            state: Cell::new((f64::NAN, f64::NAN, 0.)),
+        }
+    }
+
+    fn init(&mut self, frame_t: f64) {
+        self.frame_t = frame_t;
+        self.coef = if self.time > 0. {
+            1. - (-frame_t / self.time).exp()
+        } else {
+            1.
         };
+    }
 
-        if recvr.is_some() {
-            *self = BusParam::Connected(recvr.unwrap());
+    /// next value, `target` being the current bus value.
+    /// (must be called once per frame)
+    fn next(&self, target: f64) -> f64 {
+        let (current, last_target, step) = self.state.get();
+        // not initialized, or first value : no ramp
+        if self.frame_t == 0. || self.time <= 0. || current.is_nan() {
+            self.state.set((target, target, 0.));
+            return target;
         }
+
+        let (current, step) = match self.ramp {
+            Ramp::Exponential => (current + (target - current) * self.coef, 0.),
+            Ramp::Linear => {
+                // new target : constant speed from where we are
+                let step = if target != last_target {
+                    (target - current) * self.frame_t / self.time
+                } else {
+                    step
+                };
+                // no overshoot
+                if step > 0. {
+                    ((current + step).min(target), step)
+                } else {
+                    ((current + step).max(target), step)
+                }
+            }
+        };
+        self.state.set((current, target, step));
+        current
     }
-}
 
+    /// where the ramp is (the bus value when it hasn't started)
+    fn peek(&self, target: f64) -> f64 {
+        let current = self.state.get().0;
+        if current.is_nan() { target } else { current }
+    }
+}
 
 /// Parameters from the client side.
 // #[derive(Debug, PartialEq)]
@@ -55,8 +140,10 @@ pub enum ParamValue {
     Constant(f64),
     /// an internal bus id (for adjustable parameters)
     BusValue(BusParam),
+    /// a bus value ramping to its new values, see `ParamValue::smoothed`
+    SmoothedBus(BusParam, Smoothing),
     /// formula from constant and bus values
-    Formula(Box<CalcParam>),
+    Formula(Box<dyn CalcParam>),
     /// using the Synth's default value.
     Default(f64),
 }
@@ -66,25 +153,91 @@ impl ParamValue {
         ParamValue::Default(v)
     }
 
-    fn connect(&mut self, buses: &mut pbus::BusSystem) {
+    pub fn connect(&mut self, buses: &mut pbus::BusSystem) {
 
         // sounds like a code smell..
         match *self {
-            ParamValue::BusValue(ref mut bus_param) => bus_param.connect(buses),
+            ParamValue::BusValue(ref mut bus_param) |
+            ParamValue::SmoothedBus(ref mut bus_param, _) => bus_param.connect(buses),
             ParamValue::Formula(ref mut calc_val) => calc_val.connect(buses),
             _ => {}
         }
     }
 
+    /// frame time (for formulas with time based functions)
+    pub fn init(&mut self, frame_t: f64) {
+        match *self {
+            ParamValue::SmoothedBus(_, ref mut smoothing) => smoothing.init(frame_t),
+            ParamValue::Formula(ref mut calc_val) => calc_val.init(frame_t),
+            _ => {}
+        }
+    }
+
+    /// ramps linearly to new bus values in `time` seconds instead of
+    /// stepping. (no effect if not a bus value)
+    pub fn smoothed(self, time: f64) -> ParamValue {
+        self.ramped(Ramp::Linear, time)
+    }
+
+    /// same as `smoothed`, with an exponential ramp of time constant `time`
+    pub fn smoothed_exp(self, time: f64) -> ParamValue {
+        self.ramped(Ramp::Exponential, time)
+    }
+
+    fn ramped(self, ramp: Ramp, time: f64) -> ParamValue {
+        match self {
+            ParamValue::BusValue(bus) |
+            ParamValue::SmoothedBus(bus, _) => {
+                ParamValue::SmoothedBus(bus, Smoothing::new(ramp, time))
+            }
+            other => other,
+        }
+    }
+
     pub fn value(&self) -> f64 {
         match *self {
             ParamValue::Constant(ref x) => *x,
             ParamValue::Default(ref x) => *x,
             // see the pattern ?
             ParamValue::BusValue(ref x) => x.value(),
+            ParamValue::SmoothedBus(ref x, ref smoothing) => smoothing.next(x.value()),
             ParamValue::Formula(ref x) => x.calc(),
         }
     }
+
+    /// the parameter as written in a patch : a number, a bus name or a
+    /// formula. `None` for a default value, or a formula built by hand.
+    /// ( smoothing is not written )
+    pub fn source(&self) -> Option<String> {
+        match *self {
+            ParamValue::Constant(x) => Some(x.to_string()),
+            ParamValue::BusValue(ref bus) |
+            ParamValue::SmoothedBus(ref bus, _) => Some(bus.name().to_string()),
+            ParamValue::Formula(ref calc) => calc.source().map(|s| s.to_string()),
+            ParamValue::Default(_) => None,
+        }
+    }
+
+    /// the value, without moving smoothing or formula filters forward
+    /// ( `value` is meant to be called once per frame )
+    pub fn peek(&self) -> f64 {
+        match *self {
+            ParamValue::SmoothedBus(ref x, ref smoothing) => smoothing.peek(x.value()),
+            ParamValue::Formula(ref x) => x.peek(),
+            _ => self.value(),
+        }
+    }
+}
+
+/// a bus parameter, e.g. `VolumeParams::default().volume(bus("vol").smoothed(0.02))`
+///
+/// # Panics
+/// if `name` is not a bus name ( see `param_expression::is_bus_name` )
+pub fn bus(name: &str) -> ParamValue {
+    if !is_bus_name(name) {
+        panic!("invalid bus name : {}", name);
+    }
+    ParamValue::BusValue(BusParam::NotConnected(name.to_string()))
 }
 
 impl From<f64> for ParamValue {
@@ -93,33 +246,215 @@ impl From<f64> for ParamValue {
     }
 }
 
+impl ParamValue {
+    /// a number, a bus name ( see `param_expression::is_bus_name` ), or else
+    /// a formula.
+    pub fn parse(expr: &str) -> Result<ParamValue, ParseError> {
+        let expr = expr.trim();
+        if let Ok(x) = f64::from_str(expr) {
+            Ok(ParamValue::Constant(x))
+        } else if is_bus_name(expr) {
+            Ok(bus(expr))
+        } else {
+            parse_param_expression(expr).map(ParamValue::Formula)
+        }
+    }
+}
+
+/// see `ParamValue::parse`
+impl FromStr for ParamValue {
+    type Err = ParseError;
+
+    fn from_str(expr: &str) -> Result<ParamValue, ParseError> {
+        ParamValue::parse(expr)
+    }
+}
+
+/// for the builders, e.g. `SineParams::default().frequency("lfo * 100")`.
+/// what doesn't parse is taken as a bus name, as it always was : use
+/// `str::parse` to get the error.
 impl<'a> From<&'a str> for ParamValue {
-    fn from(bus: &'a str) -> ParamValue {
-        ParamValue::BusValue(BusParam::NotConnected(bus.to_string()))
+    fn from(expr: &'a str) -> ParamValue {
+        ParamValue::parse(expr)
+            .unwrap_or_else(|_| ParamValue::BusValue(BusParam::NotConnected(expr.to_string())))
     }
 }
 
-impl From<Box<CalcParam>> for ParamValue {
-    fn from(c: Box<CalcParam>) -> ParamValue {
+impl From<Box<dyn CalcParam>> for ParamValue {
+    fn from(c: Box<dyn CalcParam>) -> ParamValue {
         ParamValue::Formula(c)
     }
 }
 
+/// how a parameter is best edited ( think UI knobs )
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    Linear,
+    /// for frequencies, times .. (min must be > 0)
+    Exponential,
+}
+
+/// range, unit and curve of a parameter. ranges are hints for front ends :
+/// constants set by name are clamped to them ( see `Parameters::set` )
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamRange {
+    pub min: f64,
+    pub max: f64,
+    pub unit: &'static str,
+    pub curve: Curve,
+}
+
+impl ParamRange {
+    pub fn new(min: f64, max: f64) -> ParamRange {
+        ParamRange {
+            min,
+            max,
+            unit: "",
+            curve: Curve::Linear,
+        }
+    }
+
+    pub fn unit(mut self, unit: &'static str) -> ParamRange {
+        self.unit = unit;
+        self
+    }
+
+    pub fn exponential(mut self) -> ParamRange {
+        self.curve = Curve::Exponential;
+        self
+    }
+
+    pub fn contains(&self, value: f64) -> bool {
+        value >= self.min && value <= self.max
+    }
+
+    pub fn clamp(&self, value: f64) -> f64 {
+        value.max(self.min).min(self.max)
+    }
+
+    /// value for a position in [0, 1] on the curve ( knob to value )
+    pub fn from_normalized(&self, x: f64) -> f64 {
+        let x = x.clamp(0., 1.);
+        match self.curve {
+            Curve::Linear => self.min + (self.max - self.min) * x,
+            Curve::Exponential => self.min * (self.max / self.min).powf(x),
+        }
+    }
+
+    /// position of a value in [0, 1] on the curve ( value to knob )
+    pub fn to_normalized(&self, value: f64) -> f64 {
+        let value = self.clamp(value);
+        match self.curve {
+            Curve::Linear => (value - self.min) / (self.max - self.min),
+            Curve::Exponential => (value / self.min).ln() / (self.max / self.min).ln(),
+        }
+    }
+}
+
+/// parameter descriptor ( see `declare_params!` )
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamDesc {
+    pub name: &'static str,
+    pub default: f64,
+    /// None : anything goes
+    pub range: Option<ParamRange>,
+}
+
+impl ParamDesc {
+    pub fn new(name: &'static str, default: f64) -> ParamDesc {
+        ParamDesc {
+            name,
+            default,
+            range: None,
+        }
+    }
+
+    pub fn range(mut self, range: ParamRange) -> ParamDesc {
+        self.range = Some(range);
+        self
+    }
+
+    pub fn is_valid(&self, value: f64) -> bool {
+        self.range.as_ref().is_none_or(|r| r.contains(value))
+    }
+
+    /// a constant clamped to the range ( other values as they are )
+    pub fn clamp(&self, value: ParamValue) -> ParamValue {
+        match (value, self.range.as_ref()) {
+            (ParamValue::Constant(x), Some(range)) => ParamValue::Constant(range.clamp(x)),
+            (value, _) => value,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParamError {
+    UnknownParameter(String),
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParamError::UnknownParameter(ref name) => write!(f, "unknown parameter {}", name),
+        }
+    }
+}
+
+impl error::Error for ParamError {
+    fn description(&self) -> &str {
+        match *self {
+            ParamError::UnknownParameter(_) => "unknown parameter",
+        }
+    }
+}
+
 pub trait Parameters {
-    //fn list_parameters_names(&self) -> Vec<&str>;
+    /// (in the same order as `map_parameters`)
+    fn descriptors(&self) -> Vec<ParamDesc>;
     fn map_parameters(&mut self) -> Vec<&mut ParamValue>;
+
+    /// a parameter by index ( see `descriptors` ). `declare_params!` types
+    /// don't allocate here : the RT thread sets parameters with it.
+    fn parameter(&mut self, idx: usize) -> Option<&mut ParamValue> {
+        self.map_parameters().into_iter().nth(idx)
+    }
+
+    /// set a parameter by name ( for front ends that don't know the type ).
+    /// constants are clamped to the parameter range.
+    fn set(&mut self, name: &str, value: ParamValue) -> Result<(), ParamError> {
+        let descriptors = self.descriptors();
+        let idx = match descriptors.iter().position(|d| d.name == name) {
+            Some(idx) => idx,
+            None => return Err(ParamError::UnknownParameter(name.to_string())),
+        };
+        *self.map_parameters().swap_remove(idx) = descriptors[idx].clamp(value);
+        Ok(())
+    }
 }
 
 struct NoParameters;
 
 impl Parameters for NoParameters {
+    fn descriptors(&self) -> Vec<ParamDesc> {
+        Vec::new()
+    }
+
     fn map_parameters(&mut self) -> Vec<&mut ParamValue> {
         Vec::new()
     }
 }
 
+/// declares a parameter struct, with builder methods, defaults and
+/// descriptors. a range can follow the default value :
+/// ```ignore
+/// declare_params!(SineParams {
+///     amplitude: 1.0 => ParamRange::new(0., 1.),
+///     frequency: 440.0 => ParamRange::new(20., 20000.).unit("Hz").exponential(),
+///     phase: 0.,
+/// });
+/// ```
 macro_rules! declare_params {
-    ($name:ident {$($p:ident : $v:expr),*}) => {
+    ($name:ident {$($p:ident : $v:expr $(=> $r:expr)*),*}) => {
 
     //#[derive(Debug)]
     pub struct $name {
@@ -139,14 +474,17 @@ macro_rules! declare_params {
     }
 
     impl Parameters for $name {
-        /*
-        fn list_parameters_names(&self) -> Vec<&str> {
-            vec![ $( $p ,)*]
+        fn descriptors(&self) -> Vec<ParamDesc> {
+            vec![$(ParamDesc::new(stringify!($p), $v)$(.range($r))*,)*]
         }
-        */
+
         fn map_parameters(&mut self) -> Vec<&mut ParamValue> {
             vec![$(&mut self.$p,)*]
         }
+
+        fn parameter(&mut self, idx: usize) -> Option<&mut ParamValue> {
+            IntoIterator::into_iter([$(&mut self.$p,)*]).nth(idx)
+        }
     }
 
     impl $name {
@@ -160,16 +498,27 @@ macro_rules! declare_params {
         )*
     }
 };
-    ($name:ident {$($p:ident : $v:expr,)*}) => {
-        declare_params!($name { $($p : $v),* });
+    ($name:ident {$($p:ident : $v:expr $(=> $r:expr)*,)*}) => {
+        declare_params!($name { $($p : $v $(=> $r)*),* });
     };
 }
 
+/// for parameterless nodes ( see `Registry::register_synth` )
+impl Parameters for () {
+    fn descriptors(&self) -> Vec<ParamDesc> {
+        Vec::new()
+    }
+
+    fn map_parameters(&mut self) -> Vec<&mut ParamValue> {
+        Vec::new()
+    }
+}
+
 static mut NO_PARAMETERS: NoParameters = NoParameters {};
 
 pub trait Parametrized {
-    fn get_parameters(&mut self) -> &mut Parameters {
-        unsafe { &mut NO_PARAMETERS }
+    fn get_parameters(&mut self) -> &mut dyn Parameters {
+        unsafe { &mut *ptr::addr_of_mut!(NO_PARAMETERS) }
     }
 
     /// connects bus parameters to
@@ -178,21 +527,32 @@ pub trait Parametrized {
             p.connect(buses);
         }
     }
+
+    fn init_parameters(&mut self, frame_t: f64) {
+        for p in self.get_parameters().map_parameters() {
+            p.init(frame_t);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use param_expression::parse_param_expression;
 
     declare_params!(SomeParams { a: 42.0, b: 77.0 });
 
+    declare_params!(RangedParams {
+        freq: 440. => ParamRange::new(20., 20000.).unit("Hz").exponential(),
+        anything: 1.,
+        mix: 0.5 => ParamRange::new(0., 1.),
+    });
+
     #[derive(Default)]
     struct Chombier(SomeParams);
 
     impl Parametrized for Chombier {
-        fn get_parameters(&mut self) -> &mut Parameters {
-            return &mut self.0;
+        fn get_parameters(&mut self) -> &mut dyn Parameters {
+            &mut self.0
         }
     }
 
@@ -243,4 +603,170 @@ mod tests {
         assert_eq!(c.tic(), 77.0 + 14.0);
 
     }
+
+    #[test]
+    fn test_descriptors() {
+        let mut p = RangedParams::default();
+        let desc = p.descriptors();
+        let names: Vec<&str> = desc.iter().map(|d| d.name).collect();
+        assert_eq!(names, vec!["freq", "anything", "mix"]);
+        assert_eq!(desc.len(), p.map_parameters().len());
+
+        assert_eq!(desc[0].default, 440.);
+        assert_eq!(desc[0].range.as_ref().unwrap().unit, "Hz");
+        assert!(desc[0].is_valid(1000.));
+        assert!(!desc[0].is_valid(10.));
+        assert!(desc[1].is_valid(-1e9));
+        assert_eq!(desc[1].range, None);
+        assert!(!desc[2].is_valid(1.5));
+
+        assert_eq!(SomeParams::default().descriptors()[1], ParamDesc::new("b", 77.));
+        assert!(NoParameters.descriptors().is_empty());
+    }
+
+    #[test]
+    fn test_set_by_name() {
+        let mut p = RangedParams::default();
+        p.set("mix", ParamValue::from(0.25)).unwrap();
+        p.set("anything", ParamValue::parse("x * 2").unwrap()).unwrap();
+        assert_eq!(p.mix.value(), 0.25);
+        assert_eq!(p.parameter(2).map(|v| v.value()), Some(0.25));
+        assert!(p.parameter(3).is_none());
+
+        assert_eq!(
+            p.set("nope", ParamValue::from(1.)),
+            Err(ParamError::UnknownParameter("nope".to_string()))
+        );
+        // clamped to the range
+        p.set("freq", ParamValue::from(5.)).unwrap();
+        assert_eq!(p.freq.value(), 20.);
+        p.set("mix", ParamValue::from(3.)).unwrap();
+        assert_eq!(p.mix.value(), 1.);
+
+        // through a trait object
+        let mut c = Chombier::default();
+        c.get_parameters().set("b", ParamValue::from(1.)).unwrap();
+        assert_eq!(c.tic(), 43.);
+    }
+
+    #[test]
+    fn test_parse_param() {
+        match ParamValue::parse(" 0.5 ") {
+            Ok(ParamValue::Constant(x)) => assert_eq!(x, 0.5),
+            _ => panic!("not a constant"),
+        }
+        match ParamValue::parse("/synth/1") {
+            Ok(ParamValue::BusValue(BusParam::NotConnected(ref name))) => {
+                assert_eq!(name, "/synth/1")
+            }
+            _ => panic!("not a bus"),
+        }
+        assert!(ParamValue::parse("x +").is_err());
+    }
+
+    #[test]
+    fn test_param_range() {
+        let linear = ParamRange::new(-1., 1.);
+        assert_eq!(linear.from_normalized(0.75), 0.5);
+        assert_eq!(linear.to_normalized(0.5), 0.75);
+        assert_eq!(linear.clamp(3.), 1.);
+
+        let exp = ParamRange::new(10., 1000.).exponential();
+        assert!((exp.from_normalized(0.5) - 100.).abs() < 1e-9);
+        assert!((exp.to_normalized(100.) - 0.5).abs() < 1e-9);
+        assert_eq!(exp.from_normalized(2.), 1000.);
+    }
+
+    #[test]
+    fn test_smoothed_bus() {
+        let mut buses = pbus::BusSystem::new();
+        let mut linear = bus("x").smoothed(0.004);
+        let mut exp = bus("x").smoothed_exp(0.01);
+        for p in [&mut linear, &mut exp] {
+            p.connect(&mut buses);
+            p.init(0.001);
+        }
+
+        // no ramp from 0 to the first value
+        buses.publish("x", 1.).unwrap();
+        assert_eq!(1., linear.value());
+        assert_eq!(1., exp.value());
+
+        // 4 frames to get there
+        buses.publish("x", 3.).unwrap();
+        for expected in [1.5, 2., 2.5, 3., 3.] {
+            assert_eq!(expected, linear.value());
+        }
+        let e = exp.value();
+        assert!((e - (3. - 2. * (-0.1f64).exp())).abs() < 1e-9);
+
+        // retargeting mid ramp : from where it is
+        buses.publish("x", 1.).unwrap();
+        assert_eq!(2.5, linear.value());
+        buses.publish("x", 2.).unwrap();
+        assert_eq!(2.375, linear.value());
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut buses = pbus::BusSystem::new();
+        let mut smoothed = bus("x").smoothed(0.004);
+        let mut lag: ParamValue = "lag(x, 0.01)".parse().unwrap();
+        for p in [&mut smoothed, &mut lag] {
+            p.connect(&mut buses);
+            p.init(0.001);
+        }
+        buses.publish("x", 1.).unwrap();
+        assert_eq!(1., smoothed.peek());
+        smoothed.value();
+        lag.value();
+
+        buses.publish("x", 3.).unwrap();
+        assert_eq!(1.5, smoothed.value());
+        // peeking doesn't move the ramp or the filter
+        assert_eq!(1.5, smoothed.peek());
+        assert_eq!(1.5, smoothed.peek());
+        assert_eq!(2., smoothed.value());
+        let l = lag.peek();
+        assert_eq!(l, lag.peek());
+        assert_eq!(l, lag.value());
+    }
+
+    #[test]
+    fn test_smoothed_not_a_bus() {
+        // not initialized : no smoothing
+        let mut buses = pbus::BusSystem::new();
+        let mut p = bus("y").smoothed(1.);
+        p.connect(&mut buses);
+        buses.publish("y", 3.).unwrap();
+        assert_eq!(3., p.value());
+        buses.publish("y", 5.).unwrap();
+        assert_eq!(5., p.value());
+
+        // nothing to smooth
+        assert_eq!(2., ParamValue::from(2.).smoothed(1.).value());
+    }
+
+    #[test]
+    fn test_param_from_str() {
+        let mut c = Chombier(SomeParams::default().a("/voice/1/freq").b(
+            "lfo_2 * 2",
+        ));
+
+        let mut bus = pbus::BusSystem::new();
+        c.connect_parameters(&mut bus);
+        bus.publish("/voice/1/freq", 3.).unwrap();
+        bus.publish("lfo_2", 4.).unwrap();
+        assert_eq!(c.tic(), 11.0);
+    }
+
+    #[test]
+    fn test_param_from_invalid_str() {
+        // neither a bus name nor an expression
+        assert!("x +".parse::<ParamValue>().is_err());
+        match ParamValue::from("x +") {
+            ParamValue::BusValue(BusParam::NotConnected(ref name)) => assert_eq!(name, "x +"),
+            _ => panic!("should be a bus"),
+        }
+    }
 }
@@ -0,0 +1,1056 @@
+//! Patch files : a textual description of a tree, one statement per line.
+//!
+//! ```text
+//! # a drone, sent to a chorus
+//! return wet
+//! efx wet chorus
+//! mixer drone root
+//! synth drone sine frequency=110 amplitude=drone_amp
+//! synth drone sine frequency="drone_freq * 1.5" amplitude=0.2
+//! efx drone volume volume=0.5
+//! send drone wet 0.3
+//! ```
+//!
+//! * `mixer <name> [<parent>]` a mixer node, under `root` by default
+//! * `return <name>` a return mixer
+//! * `synth <mixer> <type> [<param>=<value> ..]` a synth ( see `registry` )
+//! * `efx <mixer> <type> [<param>=<value> ..]` an effect, in chain order
+//! * `send <mixer> <return> <amount>`
+//!
+//! parameter values are numbers, bus names or quoted formulas. a node can be
+//! named after its type ( `sine:lead` ) so that `diff` can follow it when
+//! its parameters are edited. a mixer has to be declared before it is used.
+//! lines starting with `#` are comments (they are not kept when a patch is
+//! written back).
+//!
+//! # Example
+//! ```
+//! use moomoot::patch::Patch;
+//!
+//! let patch: Patch = "mixer lead\nsynth lead sine frequency=\"mtof(note)\"".parse().unwrap();
+//! assert_eq!(patch.to_string().parse::<Patch>().unwrap(), patch);
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+use nom::IResult::*;
+
+use params::ParamValue;
+use param_expression::is_bus_name;
+use registry::{NamedParams, RegistryError};
+use snapshot::{MixerSnapshot, NodeSnapshot, TreeSnapshot};
+
+/// a synth or an effect : its registry type and parameters (as written)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: String,
+    /// to keep track of the node when the patch is edited ( `sine:lead` )
+    pub name: Option<String>,
+    pub params: Vec<(String, String)>,
+}
+
+impl Node {
+    /// parameters as `ParamValue`s, for the registry
+    pub fn named_params(&self) -> NamedParams {
+        self.params
+            .iter()
+            .map(|(name, value)| (name.clone(), param_value(value)))
+            .collect()
+    }
+}
+
+/// a parameter value as written in a patch : a number, a bus or a formula
+pub fn param_value(value: &str) -> ParamValue {
+    // (values are checked when the patch is parsed)
+    ParamValue::from(value)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Mixer { name: String, parent: String },
+    Return { name: String },
+    Synth { mixer: String, node: Node },
+    Efx { mixer: String, node: Node },
+    Send {
+        from: String,
+        to: String,
+        amount: String,
+    },
+}
+
+/// a parsed patch file ( `FromStr` to read, `Display` to write )
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Patch {
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    /// line (from 1), what's wrong
+    Syntax(usize, String),
+    UnknownMixer(String),
+    DuplicateMixer(String),
+    /// mixer, node name
+    DuplicateNode(String, String),
+    /// from, to : a send goes to a return mixer, once
+    InvalidSend(String, String),
+    /// a node that the registry can't build, in this mixer
+    Node(String, RegistryError),
+    /// no room left for the mixers ( see `Capacities` )
+    TooManyMixers,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PatchError::Syntax(line, ref what) => write!(f, "line {}: {}", line, what),
+            PatchError::UnknownMixer(ref name) => write!(f, "unknown mixer {}", name),
+            PatchError::DuplicateMixer(ref name) => write!(f, "mixer {} already exists", name),
+            PatchError::DuplicateNode(ref mixer, ref name) => {
+                write!(f, "node {} already exists in mixer {}", name, mixer)
+            }
+            PatchError::InvalidSend(ref from, ref to) => {
+                write!(f, "invalid send from {} to {}", from, to)
+            }
+            PatchError::Node(ref mixer, ref err) => write!(f, "in mixer {}: {}", mixer, err),
+            PatchError::TooManyMixers => f.write_str("too many mixers"),
+        }
+    }
+}
+
+impl error::Error for PatchError {
+    fn description(&self) -> &str {
+        "invalid patch"
+    }
+}
+
+// a line is a list of words : plain values, or `name=value` parameters
+#[derive(Debug, PartialEq)]
+enum Word<'a> {
+    Plain(&'a str),
+    Param(&'a str, &'a str),
+}
+
+fn is_token_char(c: char) -> bool {
+    !c.is_whitespace() && c != '"' && c != '=' && c != ';'
+}
+
+fn is_quoted_char(c: char) -> bool {
+    c != '"' && c != ';'
+}
+
+fn is_mixer_name(name: &str) -> bool {
+    !name.is_empty() &&
+        name.chars().all(
+            |c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.',
+        )
+}
+
+named!(token_p<&str, &str>, take_while1!(is_token_char));
+
+named!(quoted_p<&str, &str>, delimited!(char!('"'), take_while!(is_quoted_char), char!('"')));
+
+named!(value_p<&str, &str>, alt!(quoted_p | token_p));
+
+named!(word_p<&str, Word<'_>>, alt!(
+    ws!(do_parse!(name: token_p >> char!('=') >> value: value_p >> (Word::Param(name, value)))) |
+    map!(ws!(value_p), Word::Plain)
+));
+
+// (';' terminated, see param_expression)
+named!(line_p<&str, Vec<Word<'_>>>, terminated!(many0!(word_p), ws!(char!(';'))));
+
+fn check_value(value: &str) -> Result<String, String> {
+    ParamValue::parse(value)
+        .map(|_| value.to_string())
+        .map_err(|err| err.to_string())
+}
+
+fn mixer_name(word: Option<&Word>) -> Result<String, String> {
+    match word {
+        Some(&Word::Plain(name)) if is_mixer_name(name) => Ok(name.to_string()),
+        Some(&Word::Plain(name)) => Err(format!("invalid mixer name \"{}\"", name)),
+        _ => Err("expected a mixer name".to_string()),
+    }
+}
+
+fn node(words: &[Word]) -> Result<(String, Node), String> {
+    let mixer = mixer_name(words.get(1))?;
+    let (kind, name) = match words.get(2) {
+        Some(&Word::Plain(kind)) => {
+            match kind.find(':') {
+                Some(idx) if is_mixer_name(&kind[idx + 1..]) => {
+                    (kind[..idx].to_string(), Some(kind[idx + 1..].to_string()))
+                }
+                Some(_) => return Err(format!("invalid node name in \"{}\"", kind)),
+                None => (kind.to_string(), None),
+            }
+        }
+        _ => return Err("expected a node type".to_string()),
+    };
+    let mut params = Vec::new();
+    for word in words[3..].iter() {
+        match *word {
+            Word::Param(name, value) => {
+                let value = check_value(value).map_err(|err| format!("parameter {}: {}", name, err))?;
+                params.push((name.to_string(), value));
+            }
+            Word::Plain(w) => return Err(format!("expected a parameter, found \"{}\"", w)),
+        }
+    }
+    Ok((
+        mixer,
+        Node {
+            kind,
+            name,
+            params,
+        },
+    ))
+}
+
+fn statement(words: &[Word]) -> Result<Statement, String> {
+    let keyword = match words[0] {
+        Word::Plain(keyword) => keyword,
+        Word::Param(..) => return Err("expected a statement".to_string()),
+    };
+    let nb_args = match keyword {
+        "mixer" => 1..3,
+        "return" => 1..2,
+        "send" => 3..4,
+        _ => 0..words.len(),
+    };
+    if !(nb_args.start < words.len() && words.len() - 1 < nb_args.end) {
+        return Err(format!("wrong number of arguments for {}", keyword));
+    }
+
+    match keyword {
+        "mixer" => {
+            let parent = if words.len() > 2 {
+                mixer_name(words.get(2))
+            } else {
+                Ok("root".to_string())
+            };
+            let name = mixer_name(words.get(1))?;
+            if name == "root" {
+                return Err("root is already declared".to_string());
+            }
+            Ok(Statement::Mixer {
+                name,
+                parent: parent?,
+            })
+        }
+        "return" => {
+            let name = mixer_name(words.get(1))?;
+            if name == "root" {
+                return Err("root is already declared".to_string());
+            }
+            Ok(Statement::Return { name })
+        }
+        "synth" => {
+            node(words).map(|(mixer, node)| {
+                Statement::Synth {
+                    mixer,
+                    node,
+                }
+            })
+        }
+        "efx" => {
+            node(words).map(|(mixer, node)| {
+                Statement::Efx {
+                    mixer,
+                    node,
+                }
+            })
+        }
+        "send" => {
+            let amount = match words[3] {
+                Word::Plain(amount) => check_value(amount),
+                Word::Param(..) => Err("expected an amount".to_string()),
+            };
+            Ok(Statement::Send {
+                from: mixer_name(words.get(1))?,
+                to: mixer_name(words.get(2))?,
+                amount: amount?,
+            })
+        }
+        _ => Err(format!("unknown statement \"{}\"", keyword)),
+    }
+}
+
+// a statement, or none for an empty line
+fn parse_line(line: &str) -> Result<Option<Statement>, String> {
+    let terminated = format!("{};", line);
+    match line_p(&terminated) {
+        Done("", ref words) if words.is_empty() => Ok(None),
+        Done("", words) => statement(&words).map(Some),
+        _ => Err("unexpected character (quotes or '=')".to_string()),
+    }
+}
+
+impl FromStr for Patch {
+    type Err = PatchError;
+
+    fn from_str(text: &str) -> Result<Patch, PatchError> {
+        let mut statements = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+            if let Some(st) = parse_line(line).map_err(|err| PatchError::Syntax(idx + 1, err))? {
+                statements.push(st);
+            }
+        }
+        Ok(Patch { statements })
+    }
+}
+
+// numbers and bus names as is, formulas quoted
+fn format_value(value: &str) -> String {
+    if value.parse::<f64>().is_ok() || is_bus_name(value) {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value)
+    }
+}
+
+fn format_node(keyword: &str, mixer: &str, node: &Node) -> String {
+    let kind = match node.name {
+        Some(ref name) => format!("{}:{}", node.kind, name),
+        None => node.kind.clone(),
+    };
+    node.params.iter().fold(
+        format!("{} {} {}", keyword, mixer, kind),
+        |line, (name, value)| {
+            format!("{} {}={}", line, name, format_value(value))
+        },
+    )
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Statement::Mixer {
+                ref name,
+                ref parent,
+            } => write!(f, "mixer {} {}", name, parent),
+            Statement::Return { ref name } => write!(f, "return {}", name),
+            Statement::Synth {
+                ref mixer,
+                ref node,
+            } => f.write_str(&format_node("synth", mixer, node)),
+            Statement::Efx {
+                ref mixer,
+                ref node,
+            } => f.write_str(&format_node("efx", mixer, node)),
+            Statement::Send {
+                ref from,
+                ref to,
+                ref amount,
+            } => write!(f, "send {} {} {}", from, to, format_value(amount)),
+        }
+    }
+}
+
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: Vec<String> = self.statements.iter().map(|st| st.to_string()).collect();
+        if lines.is_empty() {
+            Ok(())
+        } else {
+            writeln!(f, "{}", lines.join("\n"))
+        }
+    }
+}
+
+/// the patch of a live tree ( see `MooMoot::patch` ) : its mixers and
+/// returns, their nodes with the parameters that were set, and the sends.
+/// transient mixers are left out, and so are node names. mixer names have
+/// to be unique for the patch to load.
+impl<'a> From<&'a TreeSnapshot> for Patch {
+    fn from(tree: &'a TreeSnapshot) -> Patch {
+        let mut statements = Vec::new();
+        let mut sends = Vec::new();
+        for ret in tree.returns.iter() {
+            statements.push(Statement::Return { name: ret.id.clone() });
+            mixer_statements(ret, &mut statements, &mut sends);
+        }
+        mixer_statements(&tree.root, &mut statements, &mut sends);
+        statements.extend(sends);
+        Patch { statements }
+    }
+}
+
+// what's in a mixer, and the mixers below it (sends go last, once the
+// returns are declared)
+fn mixer_statements(mixer: &MixerSnapshot, statements: &mut Vec<Statement>, sends: &mut Vec<Statement>) {
+    let node = |snapshot: &NodeSnapshot| {
+        Node {
+            kind: snapshot.type_name.clone(),
+            name: None,
+            params: snapshot.sources.clone(),
+        }
+    };
+    for synth in mixer.synths.iter() {
+        statements.push(Statement::Synth {
+            mixer: mixer.id.clone(),
+            node: node(synth),
+        });
+    }
+    for efx in mixer.efx.iter() {
+        statements.push(Statement::Efx {
+            mixer: mixer.id.clone(),
+            node: node(efx),
+        });
+    }
+    for send in mixer.sends.iter() {
+        sends.push(Statement::Send {
+            from: mixer.id.clone(),
+            to: send.to.clone(),
+            amount: send.source.clone().unwrap_or_else(|| send.amount.to_string()),
+        });
+    }
+    for sub in mixer.mixers.iter().filter(|m| !m.transient) {
+        statements.push(Statement::Mixer {
+            name: sub.id.clone(),
+            parent: mixer.id.clone(),
+        });
+        mixer_statements(sub, statements, sends);
+    }
+}
+
+/// a step from a patch to the next one ( see `diff` ). nodes are known by
+/// their key in their mixer : their name, or their type and rank among the
+/// unnamed nodes of this type ( `sine#0` ).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// a mixer or a return, with everything in it
+    RemoveMixer { name: String },
+    RemoveNode { mixer: String, key: String },
+    RemoveSend { from: String, to: String },
+    AddMixer { name: String, parent: String },
+    AddReturn { name: String },
+    AddSynth {
+        mixer: String,
+        key: String,
+        node: Node,
+    },
+    /// at the end of the effect chain
+    AddEfx {
+        mixer: String,
+        key: String,
+        node: Node,
+    },
+    /// new parameter values ( `None` : back to the default )
+    SetSynthParams {
+        mixer: String,
+        key: String,
+        node: Node,
+        params: Vec<(String, Option<String>)>,
+    },
+    SetEfxParams {
+        mixer: String,
+        key: String,
+        node: Node,
+        params: Vec<(String, Option<String>)>,
+    },
+    /// a new send, or a new amount
+    SetSend {
+        from: String,
+        to: String,
+        amount: String,
+    },
+}
+
+// a mixer of a patch, with what's in it
+struct MixerView<'a> {
+    // None for a return
+    parent: Option<&'a str>,
+    synths: Vec<(String, &'a Node)>,
+    efx: Vec<(String, &'a Node)>,
+    sends: Vec<(&'a str, &'a str)>, // (return, amount)
+}
+
+impl<'a> MixerView<'a> {
+    fn new(parent: Option<&'a str>) -> MixerView<'a> {
+        MixerView {
+            parent,
+            synths: Vec::new(),
+            efx: Vec::new(),
+            sends: Vec::new(),
+        }
+    }
+}
+
+// the mixers of a patch in declaration order, "root" first
+struct View<'a> {
+    order: Vec<&'a str>,
+    mixers: HashMap<&'a str, MixerView<'a>>,
+}
+
+fn find<'a, 'b>(
+    mixers: &'b mut HashMap<&'a str, MixerView<'a>>,
+    name: &str,
+) -> Result<&'b mut MixerView<'a>, PatchError> {
+    mixers.get_mut(name).ok_or(
+        PatchError::UnknownMixer(name.to_string()),
+    )
+}
+
+// checks that the mixers exist, and gives keys to the nodes
+fn view<'a>(patch: &'a Patch) -> Result<View<'a>, PatchError> {
+    let mut order = vec!["root"];
+    let mut mixers = HashMap::new();
+    mixers.insert("root", MixerView::new(Some("")));
+    let mut ranks: HashMap<(&str, &str), usize> = HashMap::new();
+
+    for st in patch.statements.iter() {
+        match *st {
+            Statement::Mixer {
+                ref name,
+                ref parent,
+            } => {
+                if !mixers.contains_key(parent.as_str()) {
+                    return Err(PatchError::UnknownMixer(parent.clone()));
+                }
+                if mixers.contains_key(name.as_str()) {
+                    return Err(PatchError::DuplicateMixer(name.clone()));
+                }
+                mixers.insert(name, MixerView::new(Some(parent)));
+                order.push(name);
+            }
+            Statement::Return { ref name } => {
+                if mixers.contains_key(name.as_str()) {
+                    return Err(PatchError::DuplicateMixer(name.clone()));
+                }
+                mixers.insert(name, MixerView::new(None));
+                order.push(name);
+            }
+            Statement::Synth {
+                ref mixer,
+                ref node,
+            } |
+            Statement::Efx {
+                ref mixer,
+                ref node,
+            } => {
+                let key = match node.name {
+                    Some(ref name) => name.clone(),
+                    None => {
+                        let rank = ranks.entry((mixer.as_str(), node.kind.as_str())).or_insert(0);
+                        *rank += 1;
+                        format!("{}#{}", node.kind, *rank - 1)
+                    }
+                };
+                let view = find(&mut mixers, mixer)?;
+                if view.synths.iter().chain(view.efx.iter()).any(|n| n.0 == key) {
+                    return Err(PatchError::DuplicateNode(mixer.clone(), key));
+                }
+                match *st {
+                    Statement::Synth { .. } => view.synths.push((key, node)),
+                    _ => view.efx.push((key, node)),
+                }
+            }
+            Statement::Send {
+                ref from,
+                ref to,
+                ref amount,
+            } => {
+                let is_return = find(&mut mixers, to)?.parent.is_none();
+                let view = find(&mut mixers, from)?;
+                if !is_return || from == to || view.sends.iter().any(|s| s.0 == to) {
+                    return Err(PatchError::InvalidSend(from.clone(), to.clone()));
+                }
+                view.sends.push((to, amount));
+            }
+        }
+    }
+    Ok(View {
+        order,
+        mixers,
+    })
+}
+
+// changed parameters
+fn param_changes(old: &Node, new: &Node) -> Vec<(String, Option<String>)> {
+    let value = |node: &Node, name: &str| {
+        node.params.iter().rev().find(|p| p.0 == name).map(
+            |p| p.1.clone(),
+        )
+    };
+    let mut changes = Vec::new();
+    for (name, v) in new.params.iter() {
+        if value(old, name).as_ref() != Some(v) && !changes.iter().any(|c: &(String, Option<String>)| c.0 == *name) {
+            changes.push((name.clone(), value(new, name)));
+        }
+    }
+    for (name, _) in old.params.iter() {
+        if value(new, name).is_none() && !changes.iter().any(|c| c.0 == *name) {
+            changes.push((name.clone(), None));
+        }
+    }
+    changes
+}
+
+// nodes of a mixer that is kept
+fn node_changes(
+    mixer: &str,
+    old: &MixerView,
+    new: &MixerView,
+    removes: &mut Vec<Change>,
+    changes: &mut Vec<Change>,
+) {
+    for &(ref key, node) in new.synths.iter() {
+        match old.synths.iter().find(|n| n.0 == *key) {
+            Some(&(_, old_node)) if old_node.kind == node.kind => {
+                let params = param_changes(old_node, node);
+                if !params.is_empty() {
+                    changes.push(Change::SetSynthParams {
+                        mixer: mixer.to_string(),
+                        key: key.clone(),
+                        node: node.clone(),
+                        params,
+                    });
+                }
+                continue;
+            }
+            Some(_) => {
+                removes.push(Change::RemoveNode {
+                    mixer: mixer.to_string(),
+                    key: key.clone(),
+                })
+            }
+            None => (),
+        }
+        changes.push(Change::AddSynth {
+            mixer: mixer.to_string(),
+            key: key.clone(),
+            node: node.clone(),
+        });
+    }
+    for (key, _) in old.synths.iter() {
+        if !new.synths.iter().any(|n| n.0 == *key) {
+            removes.push(Change::RemoveNode {
+                mixer: mixer.to_string(),
+                key: key.clone(),
+            });
+        }
+    }
+
+    // effects are in a chain : the ones after a change are rebuilt
+    let same = old.efx
+        .iter()
+        .zip(new.efx.iter())
+        .take_while(|&(o, n)| o.0 == n.0 && o.1.kind == n.1.kind)
+        .count();
+    for (&(_, old_node), &(ref key, node)) in old.efx.iter().zip(new.efx.iter()).take(same) {
+        let params = param_changes(old_node, node);
+        if !params.is_empty() {
+            changes.push(Change::SetEfxParams {
+                mixer: mixer.to_string(),
+                key: key.clone(),
+                node: node.clone(),
+                params,
+            });
+        }
+    }
+    for (key, _) in old.efx[same..].iter() {
+        removes.push(Change::RemoveNode {
+            mixer: mixer.to_string(),
+            key: key.clone(),
+        });
+    }
+    for &(ref key, node) in new.efx[same..].iter() {
+        changes.push(Change::AddEfx {
+            mixer: mixer.to_string(),
+            key: key.clone(),
+            node: node.clone(),
+        });
+    }
+}
+
+/// what has to be done to go from the `old` patch to the `new` one, leaving
+/// alone what has not changed. removals come first, sends last.
+///
+/// a mixer that moved to another parent (or became a return) is rebuilt,
+/// with everything below it.
+pub fn diff(old: &Patch, new: &Patch) -> Result<Vec<Change>, PatchError> {
+    let old_view = view(old)?;
+    let new_view = view(new)?;
+
+    // kept : same kind, same parent, and the parent is kept
+    let mut kept = HashSet::new();
+    kept.insert("root");
+    for &name in new_view.order.iter() {
+        let parent = new_view.mixers[name].parent;
+        let same = match old_view.mixers.get(name) {
+            Some(old_mixer) => old_mixer.parent == parent,
+            None => false,
+        };
+        if same && parent.map(|p| kept.contains(p)).unwrap_or(true) {
+            kept.insert(name);
+        }
+    }
+
+    let mut removes = Vec::new();
+    let mut changes = Vec::new();
+    let mut sends = Vec::new();
+
+    // (removing a mixer removes what's below it)
+    for &name in old_view.order.iter() {
+        let parent = old_view.mixers[name].parent;
+        if !kept.contains(name) && parent.map(|p| kept.contains(p)).unwrap_or(true) {
+            removes.push(Change::RemoveMixer { name: name.to_string() });
+        }
+    }
+
+    for &name in new_view.order.iter() {
+        let mixer = &new_view.mixers[name];
+        let old_mixer = if kept.contains(name) {
+            Some(&old_view.mixers[name])
+        } else {
+            None
+        };
+
+        match old_mixer {
+            Some(old_mixer) => {
+                node_changes(name, old_mixer, mixer, &mut removes, &mut changes);
+                for &(to, _) in old_mixer.sends.iter() {
+                    if kept.contains(to) && !mixer.sends.iter().any(|s| s.0 == to) {
+                        removes.push(Change::RemoveSend {
+                            from: name.to_string(),
+                            to: to.to_string(),
+                        });
+                    }
+                }
+            }
+            None => {
+                changes.push(match mixer.parent {
+                    Some(parent) => Change::AddMixer {
+                        name: name.to_string(),
+                        parent: parent.to_string(),
+                    },
+                    None => Change::AddReturn { name: name.to_string() },
+                });
+                node_changes(name, &MixerView::new(None), mixer, &mut removes, &mut changes);
+            }
+        }
+
+        for &(to, amount) in mixer.sends.iter() {
+            let unchanged = match old_mixer {
+                Some(old_mixer) if kept.contains(to) => old_mixer.sends.contains(&(to, amount)),
+                _ => false,
+            };
+            if !unchanged {
+                sends.push(Change::SetSend {
+                    from: name.to_string(),
+                    to: to.to_string(),
+                    amount: amount.to_string(),
+                });
+            }
+        }
+    }
+
+    removes.extend(changes);
+    removes.extend(sends);
+    Ok(removes)
+}
+
+/// polls a patch file for changes ( for `MooMoot::reload_patch` )
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl Watcher {
+    pub fn new<P: AsRef<Path>>(path: P) -> Watcher {
+        Watcher {
+            path: path.as_ref().to_path_buf(),
+            modified: None,
+        }
+    }
+
+    /// the patch, when the file has changed since the last call (and on the
+    /// first call). nothing while the file can't be read.
+    pub fn poll(&mut self) -> Option<Result<Patch, PatchError>> {
+        let modified = match fs::metadata(&self.path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(_) => return None,
+        };
+        if self.modified == Some(modified) {
+            return None;
+        }
+
+        let mut text = String::new();
+        match File::open(&self.path).and_then(|mut f| f.read_to_string(&mut text)) {
+            Ok(_) => {
+                self.modified = Some(modified);
+                Some(text.parse())
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "
+# a drone
+return wet
+efx wet chorus
+mixer drone
+  synth drone sine frequency = 110 amplitude=drone_amp
+synth drone sine frequency=\"drone_freq * 1.5\" amplitude=0.2
+mixer sub drone
+send drone wet 0.3
+";
+
+    #[test]
+    fn test_parse_patch() {
+        let patch: Patch = PATCH.parse().unwrap();
+        assert_eq!(patch.statements.len(), 7);
+        assert_eq!(
+            patch.statements[2],
+            Statement::Mixer {
+                name: "drone".to_string(),
+                parent: "root".to_string(),
+            }
+        );
+        assert_eq!(
+            patch.statements[4],
+            Statement::Synth {
+                mixer: "drone".to_string(),
+                node: Node {
+                    kind: "sine".to_string(),
+                    name: None,
+                    params: vec![
+                        ("frequency".to_string(), "drone_freq * 1.5".to_string()),
+                        ("amplitude".to_string(), "0.2".to_string()),
+                    ],
+                },
+            }
+        );
+        assert_eq!(
+            patch.statements[5].to_string(),
+            "mixer sub drone"
+        );
+        assert_eq!(
+            patch.statements[3].to_string(),
+            "synth drone sine frequency=110 amplitude=drone_amp"
+        );
+
+        // written back, read again
+        assert_eq!(patch.to_string().parse::<Patch>().unwrap(), patch);
+    }
+
+    #[test]
+    fn test_named_params() {
+        let patch: Patch = PATCH.parse().unwrap();
+        let params = match patch.statements[4] {
+            Statement::Synth { ref node, .. } => node.named_params(),
+            _ => panic!("not a synth"),
+        };
+        match (&params[0].1, &params[1].1) {
+            (&ParamValue::Formula(_), &ParamValue::Constant(x)) => assert_eq!(x, 0.2),
+            _ => panic!("expected a formula and a number"),
+        }
+    }
+
+    #[test]
+    fn test_patch_errors() {
+        fn error(text: &str) -> PatchError {
+            text.parse::<Patch>().unwrap_err()
+        }
+        match error("mixer a\nmixer b a\nsynth b sine frequency=\"2 +\"") {
+            PatchError::Syntax(3, _) => (),
+            e => panic!("{:?}", e),
+        }
+        match error("synth a sine frequency=\"2") {
+            PatchError::Syntax(1, _) => (),
+            e => panic!("{:?}", e),
+        }
+        assert_eq!(
+            error("\nfoo a"),
+            PatchError::Syntax(2, "unknown statement \"foo\"".to_string())
+        );
+        match error("send a b") {
+            PatchError::Syntax(1, _) => (),
+            e => panic!("{:?}", e),
+        }
+        match error("mixer root") {
+            PatchError::Syntax(1, _) => (),
+            e => panic!("{:?}", e),
+        }
+        match error("efx a volume 0.5") {
+            PatchError::Syntax(1, _) => (),
+            e => panic!("{:?}", e),
+        }
+    }
+
+    fn diff_text(old: &str, new: &str) -> Result<Vec<Change>, PatchError> {
+        diff(&old.parse().unwrap(), &new.parse().unwrap())
+    }
+
+    #[test]
+    fn test_patch_checks() {
+        assert_eq!(
+            diff_text("", "synth lead sine"),
+            Err(PatchError::UnknownMixer("lead".to_string()))
+        );
+        assert_eq!(
+            diff_text("", "mixer a\nreturn a"),
+            Err(PatchError::DuplicateMixer("a".to_string()))
+        );
+        assert_eq!(
+            diff_text("", "synth root sine:a\nefx root volume:a"),
+            Err(PatchError::DuplicateNode("root".to_string(), "a".to_string()))
+        );
+        assert_eq!(
+            diff_text("", "mixer a\nmixer b\nsend a b 0.5"),
+            Err(PatchError::InvalidSend("a".to_string(), "b".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = "
+mixer lead
+synth lead sine frequency=220 amplitude=0.5
+synth lead sine:high frequency=880
+efx lead volume volume=0.5
+efx lead pan
+mixer bass
+synth bass karplus
+return wet
+send lead wet 0.3
+";
+        // nothing changed
+        assert_eq!(diff_text(old, old), Ok(vec![]));
+
+        let new = "
+return wet
+mixer lead
+synth lead sine frequency=220
+synth lead sine:high frequency=\"880 * vibrato\"
+synth lead noise
+efx lead volume volume=0.6
+efx lead chorus
+efx lead pan
+send lead wet 0.5
+";
+        let changes = diff_text(old, new).unwrap();
+        let node = |text: &str| match text.parse::<Patch>().unwrap().statements[0] {
+            Statement::Synth { ref node, .. } |
+            Statement::Efx { ref node, .. } => node.clone(),
+            _ => unreachable!(),
+        };
+        let s = |x: &str| x.to_string();
+        assert_eq!(
+            changes,
+            vec![
+                Change::RemoveMixer { name: s("bass") },
+                Change::RemoveNode {
+                    mixer: s("lead"),
+                    key: s("pan#0"),
+                },
+                Change::SetSynthParams {
+                    mixer: s("lead"),
+                    key: s("sine#0"),
+                    node: node("synth lead sine frequency=220"),
+                    params: vec![(s("amplitude"), None)],
+                },
+                Change::SetSynthParams {
+                    mixer: s("lead"),
+                    key: s("high"),
+                    node: node("synth lead sine:high frequency=\"880 * vibrato\""),
+                    params: vec![(s("frequency"), Some(s("880 * vibrato")))],
+                },
+                Change::AddSynth {
+                    mixer: s("lead"),
+                    key: s("noise#0"),
+                    node: node("synth lead noise"),
+                },
+                Change::SetEfxParams {
+                    mixer: s("lead"),
+                    key: s("volume#0"),
+                    node: node("efx lead volume volume=0.6"),
+                    params: vec![(s("volume"), Some(s("0.6")))],
+                },
+                Change::AddEfx {
+                    mixer: s("lead"),
+                    key: s("chorus#0"),
+                    node: node("efx lead chorus"),
+                },
+                Change::AddEfx {
+                    mixer: s("lead"),
+                    key: s("pan#0"),
+                    node: node("efx lead pan"),
+                },
+                Change::SetSend {
+                    from: s("lead"),
+                    to: s("wet"),
+                    amount: s("0.5"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_moved_mixer() {
+        let old = "mixer a\nmixer b a\nsynth b sine\nmixer c b\nreturn wet\nsend c wet 1";
+        let new = "mixer a\nmixer b\nsynth b sine\nmixer c b\nreturn wet\nsend c wet 1";
+        let s = |x: &str| x.to_string();
+        // b moved : b and c are rebuilt, the send from c is set again
+        let changes = diff_text(old, new).unwrap();
+        assert_eq!(changes[0], Change::RemoveMixer { name: s("b") });
+        assert_eq!(
+            changes[1],
+            Change::AddMixer {
+                name: s("b"),
+                parent: s("root"),
+            }
+        );
+        match changes[2] {
+            Change::AddSynth { ref mixer, .. } => assert_eq!(mixer, "b"),
+            ref c => panic!("{:?}", c),
+        }
+        assert_eq!(
+            changes[3],
+            Change::AddMixer {
+                name: s("c"),
+                parent: s("b"),
+            }
+        );
+        assert_eq!(
+            changes[4],
+            Change::SetSend {
+                from: s("c"),
+                to: s("wet"),
+                amount: s("1"),
+            }
+        );
+        assert_eq!(changes.len(), 5);
+    }
+
+    #[test]
+    fn test_watcher() {
+        // one file per test run, runs can overlap
+        let name = format!("moomoot-test-watcher-{}.patch", ::std::process::id());
+        let path = ::std::env::temp_dir().join(name);
+        ::std::fs::write(&path, "mixer lead\nsynth lead sine:a").unwrap();
+
+        let mut watcher = Watcher::new(&path);
+        assert_eq!(watcher.poll().unwrap().unwrap().statements.len(), 2);
+        assert_eq!(watcher.poll(), None);
+        ::std::fs::remove_file(&path).unwrap();
+        assert_eq!(watcher.poll(), None);
+    }
+}
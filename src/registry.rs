@@ -0,0 +1,251 @@
+//! Creating synths and effects from their type name and string-keyed
+//! parameters ( for network protocols, patch files .. )
+//!
+//! # Example
+//! ```
+//! use moomoot::registry::Registry;
+//! use moomoot::ParamValue;
+//!
+//! let registry = Registry::default();
+//! let sine = registry.synth("sine", vec![
+//!     ("frequency".to_string(), ParamValue::from(220.)),
+//!     ("amplitude".to_string(), ParamValue::from("/sine/amp")),
+//! ]).unwrap();
+//! ```
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use params::{ParamDesc, ParamError, ParamValue, Parameters};
+use synth::Synth;
+use synth::sine::Sine;
+use synth::string::KarplusStrong;
+use synth::noise::WhiteNoise;
+use efx::Efx;
+use efx::volume::Volume;
+use efx::pan::Pan;
+use efx::chorus::Chorus;
+use efx::flanger::Flanger;
+use efx::phaser::Phaser;
+
+/// named parameters, applied over the defaults
+pub type NamedParams = Vec<(String, ParamValue)>;
+
+type Constructor<T> = Box<dyn Fn(NamedParams) -> Result<T, ParamError> + Send + Sync>;
+
+struct Entry<T> {
+    build: Constructor<T>,
+    params: Vec<ParamDesc>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RegistryError {
+    UnknownType(String),
+    Parameter(ParamError),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RegistryError::UnknownType(ref name) => write!(f, "unknown node type {}", name),
+            RegistryError::Parameter(ref err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for RegistryError {
+    fn description(&self) -> &str {
+        match *self {
+            RegistryError::UnknownType(_) => "unknown node type",
+            RegistryError::Parameter(_) => "invalid parameter",
+        }
+    }
+}
+
+impl From<ParamError> for RegistryError {
+    fn from(err: ParamError) -> RegistryError {
+        RegistryError::Parameter(err)
+    }
+}
+
+// defaults, then the named parameters
+fn build_params<P: Parameters + Default>(named: NamedParams) -> Result<P, ParamError> {
+    let mut params = P::default();
+    for (name, value) in named {
+        params.set(&name, value)?
+    }
+    Ok(params)
+}
+
+/// synth and effect constructors by type name.
+///
+/// `Registry::default()` knows the builtin ones : "sine", "karplus", "noise"
+/// synths and "volume", "pan", "chorus", "flanger", "phaser" effects.
+pub struct Registry {
+    synths: HashMap<String, Entry<Box<dyn Synth>>>,
+    efx: HashMap<String, Entry<Box<dyn Efx>>>,
+}
+
+impl Registry {
+    /// an empty registry
+    pub fn new() -> Registry {
+        Registry {
+            synths: HashMap::new(),
+            efx: HashMap::new(),
+        }
+    }
+
+    /// `build` gets the default parameters, updated with the named ones.
+    /// (replaces a previous registration with the same name)
+    pub fn register_synth<P, S, F>(&mut self, name: &str, build: F)
+    where
+        P: Parameters + Default + 'static,
+        S: Synth + 'static,
+        F: Fn(P) -> S + Send + Sync + 'static,
+    {
+        let entry = Entry {
+            build: Box::new(move |named| {
+                build_params(named).map(|p| Box::new(build(p)) as Box<dyn Synth>)
+            }),
+            params: P::default().descriptors(),
+        };
+        self.synths.insert(name.to_string(), entry);
+    }
+
+    /// see `register_synth`
+    pub fn register_efx<P, E, F>(&mut self, name: &str, build: F)
+    where
+        P: Parameters + Default + 'static,
+        E: Efx + 'static,
+        F: Fn(P) -> E + Send + Sync + 'static,
+    {
+        let entry = Entry {
+            build: Box::new(move |named| {
+                build_params(named).map(|p| Box::new(build(p)) as Box<dyn Efx>)
+            }),
+            params: P::default().descriptors(),
+        };
+        self.efx.insert(name.to_string(), entry);
+    }
+
+    pub fn synth(&self, type_name: &str, params: NamedParams) -> Result<Box<dyn Synth>, RegistryError> {
+        match self.synths.get(type_name) {
+            Some(entry) => (entry.build)(params).map_err(RegistryError::from),
+            None => Err(RegistryError::UnknownType(type_name.to_string())),
+        }
+    }
+
+    pub fn efx(&self, type_name: &str, params: NamedParams) -> Result<Box<dyn Efx>, RegistryError> {
+        match self.efx.get(type_name) {
+            Some(entry) => (entry.build)(params).map_err(RegistryError::from),
+            None => Err(RegistryError::UnknownType(type_name.to_string())),
+        }
+    }
+
+    /// parameters of a synth type
+    pub fn synth_params(&self, type_name: &str) -> Option<&[ParamDesc]> {
+        self.synths.get(type_name).map(|e| &e.params[..])
+    }
+
+    /// parameters of an effect type
+    pub fn efx_params(&self, type_name: &str) -> Option<&[ParamDesc]> {
+        self.efx.get(type_name).map(|e| &e.params[..])
+    }
+
+    /// registered synth types (sorted)
+    pub fn synth_types(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.synths.keys().map(|k| k.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    /// registered effect types (sorted)
+    pub fn efx_types(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.efx.keys().map(|k| k.as_str()).collect();
+        names.sort();
+        names
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        let mut r = Registry::new();
+        r.register_synth("sine", Sine::new);
+        r.register_synth("karplus", KarplusStrong::new);
+        r.register_synth("noise", |_: ()| WhiteNoise::new());
+        r.register_efx("volume", Volume::new);
+        r.register_efx("pan", Pan::new);
+        r.register_efx("chorus", Chorus::new);
+        r.register_efx("flanger", Flanger::new);
+        r.register_efx("phaser", Phaser::new);
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::{SampleValue, mono_value};
+    use params::Parametrized;
+
+    #[test]
+    fn test_builtins() {
+        let r = Registry::default();
+        assert_eq!(r.synth_types(), vec!["karplus", "noise", "sine"]);
+        assert_eq!(
+            r.efx_types(),
+            vec!["chorus", "flanger", "pan", "phaser", "volume"]
+        );
+        assert!(r.synth_params("noise").unwrap().is_empty());
+        assert_eq!(r.synth_params("sine").unwrap()[1].name, "frequency");
+
+        let mut vol = r.efx("volume", vec![("volume".to_string(), ParamValue::from(0.5))])
+            .unwrap();
+        assert_eq!(vol.sample(SampleValue::Mono(1.)), mono_value(0.5));
+    }
+
+    #[test]
+    fn test_errors() {
+        let r = Registry::default();
+        assert_eq!(
+            r.synth("organ", vec![]).err(),
+            Some(RegistryError::UnknownType("organ".to_string()))
+        );
+        assert_eq!(
+            r.synth("sine", vec![("phase".to_string(), ParamValue::from(0.))])
+                .err(),
+            Some(RegistryError::Parameter(
+                ParamError::UnknownParameter("phase".to_string()),
+            ))
+        );
+        // synths and effects are different namespaces
+        assert!(r.synth("volume", vec![]).is_err());
+    }
+
+    declare_params!(GainParams { gain: 2. });
+
+    struct Gain(GainParams);
+
+    impl Parametrized for Gain {
+        fn get_parameters(&mut self) -> &mut dyn Parameters {
+            &mut self.0
+        }
+    }
+
+    impl Efx for Gain {
+        fn sample(&mut self, sample: SampleValue) -> ::traits::SoundSample {
+            ::traits::SoundSample::Sample(sample * self.0.gain.value())
+        }
+    }
+
+    #[test]
+    fn test_register() {
+        let mut r = Registry::new();
+        r.register_efx("gain", Gain);
+        let mut g = r.efx("gain", vec![("gain".to_string(), ParamValue::from(3.))])
+            .unwrap();
+        assert_eq!(g.sample(SampleValue::Mono(1.)), mono_value(3.));
+        assert_eq!(r.efx_params("gain").unwrap()[0].default, 2.);
+    }
+}
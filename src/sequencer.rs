@@ -0,0 +1,120 @@
+//! Step sequencer : patterns played in the audio thread, in sync with a
+//! tempo clock ( see `MooMoot::add_track` ).
+
+use synth::Synth;
+
+/// something a pattern can play.
+///
+/// `voice` is called ahead of time, in a thread of the track ( see
+/// `MooMoot::add_track` ).
+pub trait Instrument: Send {
+    /// a new synth for a note step. (it is added to the track's mixer and
+    /// should return `Done` when it has finished playing)
+    fn voice(&mut self, value: f64) -> Box<dyn Synth>;
+}
+
+/// any closure building synths is an instrument
+impl<F> Instrument for F
+where
+    F: FnMut(f64) -> Box<dyn Synth> + Send,
+{
+    fn voice(&mut self, value: f64) -> Box<dyn Synth> {
+        self(value)
+    }
+}
+
+/// what happens on a step
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    /// a voice of the track's instrument
+    Note(f64),
+    /// set a bus value
+    Bus(String, f64),
+}
+
+/// a loop of steps. (it has at least one step : the RT thread plays it
+/// modulo its length)
+/// # Example
+/// ```
+/// use moomoot::sequencer::Pattern;
+/// // 4 beats of 8th notes, a bass note on the first beat
+/// let p = Pattern::new(8, 2).note(0, 36.).bus(0, "cutoff", 800.).note(4, 43.);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    steps: Vec<Vec<Trigger>>,
+    steps_per_beat: u32,
+}
+
+impl Pattern {
+    /// (at least one step, and one step per beat)
+    pub fn new(nb_steps: usize, steps_per_beat: u32) -> Pattern {
+        Pattern {
+            steps: vec![Vec::new(); nb_steps.max(1)],
+            steps_per_beat: steps_per_beat.max(1),
+        }
+    }
+
+    /// a note on every step with a value
+    pub fn from_notes(steps_per_beat: u32, notes: &[Option<f64>]) -> Pattern {
+        notes.iter().enumerate().fold(
+            Pattern::new(notes.len(), steps_per_beat),
+            |p, (step, note)| match *note {
+                Some(value) => p.note(step, value),
+                None => p,
+            },
+        )
+    }
+
+    /// # Panics
+    /// if `step` is out of the pattern
+    pub fn note(mut self, step: usize, value: f64) -> Pattern {
+        self.steps[step].push(Trigger::Note(value));
+        self
+    }
+
+    /// # Panics
+    /// if `step` is out of the pattern
+    pub fn bus(mut self, step: usize, bus: &str, value: f64) -> Pattern {
+        self.steps[step].push(Trigger::Bus(bus.to_string(), value));
+        self
+    }
+
+    /// the triggers of each step
+    pub fn steps(&self) -> &[Vec<Trigger>] {
+        &self.steps
+    }
+
+    pub fn steps_per_beat(&self) -> u32 {
+        self.steps_per_beat
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// (never : see `new`)
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let p = Pattern::from_notes(4, &[Some(60.), None, None, Some(62.)]).bus(1, "x", 0.5);
+        assert_eq!(p.len(), 4);
+        assert_eq!(p.steps[0], vec![Trigger::Note(60.)]);
+        assert_eq!(p.steps[1], vec![Trigger::Bus("x".to_string(), 0.5)]);
+        assert!(p.steps[2].is_empty());
+        assert_eq!(p.steps[3], vec![Trigger::Note(62.)]);
+
+        // never empty
+        let p = Pattern::from_notes(0, &[]);
+        assert_eq!(p.len(), 1);
+        assert_eq!(p.steps_per_beat(), 1);
+    }
+}
@@ -0,0 +1,91 @@
+//! A picture of the live tree, taken in the RT thread ( see
+//! `MooMoot::snapshot` ), for debugging, UIs and tests.
+
+/// a synth or an effect
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeSnapshot {
+    /// for nodes created from a patch
+    pub id: Option<String>,
+    pub type_name: String,
+    /// current parameter values
+    pub params: Vec<(String, f64)>,
+    /// the parameters that were set, as written in a patch ( see
+    /// `ParamValue::source` )
+    pub sources: Vec<(String, String)>,
+}
+
+impl NodeSnapshot {
+    pub fn param(&self, name: &str) -> Option<f64> {
+        self.params.iter().find(|p| p.0 == name).map(|p| p.1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SendSnapshot {
+    /// the return mixer id
+    pub to: String,
+    pub amount: f64,
+    /// as written in a patch
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MixerSnapshot {
+    pub id: String,
+    pub transient: bool,
+    pub synths: Vec<NodeSnapshot>,
+    /// in chain order
+    pub efx: Vec<NodeSnapshot>,
+    pub sends: Vec<SendSnapshot>,
+    pub mixers: Vec<MixerSnapshot>,
+}
+
+impl MixerSnapshot {
+    /// this mixer, or one below it
+    pub fn find_mixer(&self, id: &str) -> Option<&MixerSnapshot> {
+        if self.id == id {
+            Some(self)
+        } else {
+            self.mixers.iter().filter_map(|m| m.find_mixer(id)).next()
+        }
+    }
+
+    /// this mixer and the ones below it
+    pub fn mixer_count(&self) -> usize {
+        1 + self.mixers.iter().map(|m| m.mixer_count()).sum::<usize>()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BusSnapshot {
+    pub name: String,
+    /// last published value
+    pub value: f64,
+    /// parameters listening to it
+    pub subscribers: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeSnapshot {
+    pub root: MixerSnapshot,
+    pub returns: Vec<MixerSnapshot>,
+    /// sorted by name
+    pub buses: Vec<BusSnapshot>,
+}
+
+impl TreeSnapshot {
+    /// a mixer of the main tree or of the returns
+    pub fn find_mixer(&self, id: &str) -> Option<&MixerSnapshot> {
+        self.root.find_mixer(id).or_else(|| {
+            self.returns.iter().filter_map(|r| r.find_mixer(id)).next()
+        })
+    }
+
+    pub fn mixer_count(&self) -> usize {
+        self.root.mixer_count() + self.returns.iter().map(|r| r.mixer_count()).sum::<usize>()
+    }
+
+    pub fn bus(&self, name: &str) -> Option<&BusSnapshot> {
+        self.buses.iter().find(|b| b.name == name)
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::Duration;
+use tree::pbus::{link, Reader, Sender};
+
+// rolling window, in periods
+const NB_PERIODS: usize = 64;
+
+/// how busy the RT thread is ( see `MooMoot::dsp_stats` ).
+///
+/// loads are the processing time of a JACK period over its duration : at
+/// 1.0 the callback doesn't make it in time.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DspStats {
+    /// the last period
+    pub load: f64,
+    /// over the last 64 periods
+    pub load_min: f64,
+    pub load_avg: f64,
+    pub load_max: f64,
+    /// DSP load of the whole JACK graph, as computed by JACK (0..1)
+    pub jack_load: f64,
+    pub synths: usize,
+    pub mixers: usize,
+    /// since the start
+    pub xruns: usize,
+    pub periods: u64,
+}
+
+// what the RT thread measures : `DspStats` without the xruns and the JACK
+// load, which come from the control side
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PeriodStats {
+    load: f64,
+    load_min: f64,
+    load_avg: f64,
+    load_max: f64,
+    synths: usize,
+    mixers: usize,
+    periods: u64,
+}
+
+pub fn dsp_stats(rt: PeriodStats, jack_load: f64, xruns: usize) -> DspStats {
+    DspStats {
+        load: rt.load,
+        load_min: rt.load_min,
+        load_avg: rt.load_avg,
+        load_max: rt.load_max,
+        jack_load,
+        synths: rt.synths,
+        mixers: rt.mixers,
+        xruns,
+        periods: rt.periods,
+    }
+}
+
+pub fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9
+}
+
+/// RT side : records the processing time of each period
+pub struct StatsRecorder {
+    loads: [f64; NB_PERIODS],
+    periods: u64,
+    out: Sender<PeriodStats>,
+}
+
+pub fn recorder() -> (Reader<PeriodStats>, StatsRecorder) {
+    let (out, stats) = link(PeriodStats::default());
+    (
+        stats,
+        StatsRecorder {
+            loads: [0.; NB_PERIODS],
+            periods: 0,
+            out,
+        },
+    )
+}
+
+impl StatsRecorder {
+    /// `elapsed` : processing time of a period lasting `period` seconds
+    pub fn period(&mut self, elapsed: Duration, period: f64, synths: usize, mixers: usize) {
+        let load = if period > 0. {
+            seconds(elapsed) / period
+        } else {
+            0.
+        };
+        self.loads[self.periods as usize % NB_PERIODS] = load;
+        self.periods += 1;
+
+        let loads = &self.loads[..(self.periods as usize).min(NB_PERIODS)];
+        let (min, max, sum) = loads.iter().fold(
+            (load, load, 0.),
+            |(min, max, sum), &l| (min.min(l), max.max(l), sum + l),
+        );
+        // (nobody reading is fine)
+        self.out
+            .send(PeriodStats {
+                load,
+                load_min: min,
+                load_avg: sum / loads.len() as f64,
+                load_max: max,
+                synths,
+                mixers,
+                periods: self.periods,
+            })
+            .ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_recorder() {
+        let (stats, mut rec) = recorder();
+        rec.period(Duration::from_millis(1), 0.004, 3, 2);
+        rec.period(Duration::from_millis(3), 0.004, 4, 2);
+        let s = dsp_stats(stats.value(), 0.5, 1);
+        assert_eq!(s.load, 0.75);
+        assert_eq!((s.load_min, s.load_avg, s.load_max), (0.25, 0.5, 0.75));
+        assert_eq!((s.synths, s.mixers, s.periods), (4, 2, 2));
+        assert_eq!((s.jack_load, s.xruns), (0.5, 1));
+
+        // the first ones are out of the window
+        for _ in 0..NB_PERIODS {
+            rec.period(Duration::from_millis(2), 0.004, 4, 2);
+        }
+        let s = stats.value();
+        assert_eq!((s.load_min, s.load_avg, s.load_max), (0.5, 0.5, 0.5));
+    }
+}
@@ -18,4 +18,10 @@ pub trait Synth: Parametrized + Send {
     ///
     /// This runs in the RealTime thread : no blocking !
     fn sample(&mut self) -> SoundSample;
+
+    /// name of the synth type ( for snapshots, the registry name for the
+    /// built-in ones )
+    fn type_name(&self) -> &str {
+        "unknown"
+    }
 }
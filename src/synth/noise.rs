@@ -12,6 +12,12 @@ pub struct WhiteNoise {
     rng: rand::XorShiftRng,
 }
 
+impl Default for WhiteNoise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl WhiteNoise {
     pub fn new() -> WhiteNoise {
         WhiteNoise { rng: rand::weak_rng() }
@@ -25,4 +31,8 @@ impl Synth for WhiteNoise {
         let between = Range::new(-1f64, 1.);
         mono_value(between.ind_sample(&mut self.rng))
     }
+
+    fn type_name(&self) -> &str {
+        "noise"
+    }
 }
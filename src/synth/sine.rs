@@ -7,8 +7,8 @@ use std::f64::consts::PI;
 
 
 declare_params!(SineParams {
-    amplitude: 1.0,
-    frequency: 440.0,
+    amplitude: 1.0 => ParamRange::new(0., 1.),
+    frequency: 440.0 => ParamRange::new(0.01, 20000.).unit("Hz").exponential(),
 });
 
 /// Pure sine synth.
@@ -19,8 +19,8 @@ pub struct Sine {
 }
 
 impl Parametrized for Sine {
-    fn get_parameters(&mut self) -> &mut Parameters {
-        return &mut self.params;
+    fn get_parameters(&mut self) -> &mut dyn Parameters {
+        &mut self.params
     }
 }
 
@@ -29,7 +29,7 @@ impl Sine {
         Sine {
             time: 0.0,
             frame_t: 0.0,
-            params: params,
+            params,
         }
     }
 }
@@ -45,4 +45,8 @@ impl Synth for Sine {
         self.time += self.frame_t;
         mono_value(x.sin() * self.params.amplitude.value())
     }
+
+    fn type_name(&self) -> &str {
+        "sine"
+    }
 }
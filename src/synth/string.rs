@@ -9,18 +9,17 @@ use params::*;
 
 
 declare_params!(KarplusStrongParams {
-    cutoff_freq: 6000.0,
-    base_freq: 440.0,
-    feedback_gain: 0.999,
+    cutoff_freq: 6000.0 => ParamRange::new(20., 20000.).unit("Hz").exponential(),
+    base_freq: 440.0 => ParamRange::new(1., 20000.).unit("Hz").exponential(),
+    feedback_gain: 0.999 => ParamRange::new(0., 1.),
 });
 
-/// Karplus-Strong alg. for "plucked string" sound synthesis
-/// https://en.wikipedia.org/wiki/Karplus%E2%80%93Strong_string_synthesis
-
 // WhiteNoise  ->  +  ->  ->
 //                 |      |
 //                 LP  <- delay ( one period of fundamental note)
 
+/// Karplus-Strong alg. for "plucked string" sound synthesis
+/// https://en.wikipedia.org/wiki/Karplus%E2%80%93Strong_string_synthesis
 pub struct KarplusStrong {
     params: KarplusStrongParams,
     time: f64,
@@ -38,7 +37,7 @@ impl KarplusStrong {
     // sustain : gain of the feedback 0 : non sustain - 1: inifinte
     pub fn new(params: KarplusStrongParams) -> KarplusStrong {
         KarplusStrong {
-            params: params,
+            params,
             time: 0.,
             frame_t: 0.,
             last_feedback: 0.,
@@ -61,7 +60,7 @@ impl KarplusStrong {
 }
 
 impl Parametrized for KarplusStrong {
-    fn get_parameters(&mut self) -> &mut Parameters {
+    fn get_parameters(&mut self) -> &mut dyn Parameters {
         &mut self.params
     }
 }
@@ -77,10 +76,8 @@ impl Synth for KarplusStrong {
         {
             let period = self.update_delay_line();
             if self.time < (period as f64) * self.frame_t {
-                if let SoundSample::Sample(smp) = self.noise_synt.sample() {
-                    if let SampleValue::Mono(n) = smp {
-                        current_sample += n;
-                    }
+                if let SoundSample::Sample(SampleValue::Mono(n)) = self.noise_synt.sample() {
+                    current_sample += n;
                 }
             } else {
                 if self.energy < 1e-9 {
@@ -109,4 +106,8 @@ impl Synth for KarplusStrong {
 
         mono_value(res)
     }
+
+    fn type_name(&self) -> &str {
+        "karplus"
+    }
 }
@@ -1,4 +1,4 @@
-use std::ops::{Add, AddAssign};
+use std::ops::{Add, AddAssign, Mul};
 use std::iter::Sum;
 
 #[derive(Debug, PartialEq, Clone)]
@@ -61,6 +61,18 @@ impl AddAssign for SampleValue {
     }
 }
 
+/// gain
+impl Mul<f64> for SampleValue {
+    type Output = SampleValue;
+
+    fn mul(self, gain: f64) -> SampleValue {
+        match self {
+            SampleValue::Mono(x) => SampleValue::Mono(x * gain),
+            SampleValue::Stereo(r, l) => SampleValue::Stereo(r * gain, l * gain),
+        }
+    }
+}
+
 /// A frame value. Can be an actual sound frame, or silence
 ///
 /// There is two kind of silence : `Silence` and `Done`. When a `Synth` or `Efx`
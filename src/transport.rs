@@ -0,0 +1,148 @@
+use jack::prelude as j;
+use jack_sys;
+use tree::mmtree::MMTree;
+use tree::pbus::{Bus, BusSystem};
+
+/// bars / beats / ticks position, when there is a timebase master
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bbt {
+    /// from 1 (JACK style)
+    pub bar: i32,
+    /// position in the bar, in beats (from 0)
+    pub beat: f64,
+    pub beats_per_bar: f64,
+    pub bpm: f64,
+}
+
+/// the reserved `transport.*` buses, looked up once on the control side :
+/// publishing them every period costs no lookup, with or without listeners.
+pub struct TransportBuses {
+    rolling: Bus<f64>,
+    bpm: Bus<f64>,
+    bar: Bus<f64>,
+    beat: Bus<f64>,
+}
+
+impl TransportBuses {
+    pub fn new(buses: &mut BusSystem) -> TransportBuses {
+        TransportBuses {
+            rolling: buses.bus("transport.rolling"),
+            bpm: buses.bus("transport.bpm"),
+            bar: buses.bus("transport.bar"),
+            beat: buses.bus("transport.beat"),
+        }
+    }
+}
+
+/// JACK transport, at the beginning of a period
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transport {
+    pub rolling: bool,
+    pub bbt: Option<Bbt>,
+}
+
+impl Transport {
+    pub fn query(ps: &j::ProcessScope) -> Transport {
+        let mut pos = jack_sys::jack_position_t::default();
+        let state = unsafe { jack_sys::jack_transport_query(ps.client_ptr(), &mut pos) };
+
+        // (packed struct : copy the fields before using them)
+        let valid = pos.valid;
+        let bbt = if valid & jack_sys::JackPositionBBT != 0 {
+            let (bar, beat, tick) = (pos.bar, pos.beat, pos.tick);
+            let ticks_per_beat = pos.ticks_per_beat;
+            Some(Bbt {
+                bar,
+                beat: (beat - 1) as f64 + tick as f64 / ticks_per_beat.max(1.),
+                beats_per_bar: pos.beats_per_bar as f64,
+                bpm: pos.beats_per_minute,
+            })
+        } else {
+            None
+        };
+
+        Transport {
+            rolling: state == jack_sys::JackTransportRolling,
+            bbt,
+        }
+    }
+
+    /// position from the start, in beats
+    pub fn beats(&self) -> Option<f64> {
+        self.bbt.map(
+            |bbt| (bbt.bar - 1) as f64 * bbt.beats_per_bar + bbt.beat,
+        )
+    }
+
+    pub fn bpm(&self) -> Option<f64> {
+        self.bbt.map(|bbt| bbt.bpm)
+    }
+
+    /// on the reserved `transport.*` buses : `rolling` (0 or 1), and when
+    /// there's a timebase master `bpm`, `bar` and `beat` (in the bar)
+    pub fn publish(&self, buses: &TransportBuses, tree: &mut MMTree) {
+        let rolling = if self.rolling { 1. } else { 0. };
+        tree.set_bus(&buses.rolling, rolling);
+        if let Some(bbt) = self.bbt {
+            tree.set_bus(&buses.bpm, bbt.bpm);
+            tree.set_bus(&buses.bar, bbt.bar as f64);
+            tree.set_bus(&buses.beat, bbt.beat);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transport_position() {
+        let t = Transport {
+            rolling: true,
+            bbt: Some(Bbt {
+                bar: 3,
+                beat: 1.5,
+                beats_per_bar: 4.,
+                bpm: 140.,
+            }),
+        };
+        assert_eq!(t.beats(), Some(9.5));
+        assert_eq!(t.bpm(), Some(140.));
+
+        let stopped = Transport {
+            rolling: false,
+            bbt: None,
+        };
+        assert_eq!(stopped.beats(), None);
+    }
+
+    #[test]
+    fn test_transport_buses() {
+        let mut tree = MMTree::new();
+        let buses = TransportBuses::new(tree.buses());
+        let t = Transport {
+            rolling: true,
+            bbt: Some(Bbt {
+                bar: 2,
+                beat: 0.5,
+                beats_per_bar: 4.,
+                bpm: 90.,
+            }),
+        };
+        t.publish(&buses, &mut tree);
+        let values: Vec<(String, f64)> = tree.snapshot()
+            .buses
+            .into_iter()
+            .map(|b| (b.name, b.value))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("transport.bar".to_string(), 2.),
+                ("transport.beat".to_string(), 0.5),
+                ("transport.bpm".to_string(), 90.),
+                ("transport.rolling".to_string(), 1.),
+            ]
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! slot map for the tree nodes : constant time lookup by key, and a key
+//! of a removed node never finds the node reusing its slot ( generations ).
+//!
+//! keys are handed out on the control side ( `Keys` ), so a handle can be
+//! returned before the RT side has created the node. the RT side gives the
+//! slots back once the node is dropped.
+//!
+//! the slots are reserved up front ( `Arena::with_capacity` ) : inserting
+//! and removing nodes doesn't allocate. a key past the capacity is refused,
+//! and `Keys::with_capacity` doesn't hand them out.
+
+/// where a node lives in an `Arena`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    index: usize,
+    generation: u64,
+}
+
+impl Key {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/// the root mixer, always there
+pub const ROOT: Key = Key {
+    index: 0,
+    generation: 0,
+};
+
+/// control side : allocates the keys
+#[derive(Debug, Clone)]
+pub struct Keys {
+    generations: Vec<u64>,
+    free: Vec<usize>,
+    capacity: usize,
+}
+
+impl Keys {
+    /// ( `ROOT` is taken )
+    #[cfg(test)]
+    pub fn new() -> Keys {
+        Keys::with_capacity(usize::MAX)
+    }
+
+    /// as many keys as the arena has slots
+    pub fn with_capacity(capacity: usize) -> Keys {
+        Keys {
+            generations: vec![0],
+            free: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// `None` : all the slots are in use
+    pub fn allocate(&mut self) -> Option<Key> {
+        match self.free.pop() {
+            Some(index) => {
+                self.generations[index] += 1;
+                Some(Key {
+                    index,
+                    generation: self.generations[index],
+                })
+            }
+            None if self.generations.len() < self.capacity => {
+                self.generations.push(0);
+                Some(Key {
+                    index: self.generations.len() - 1,
+                    generation: 0,
+                })
+            }
+            None => None,
+        }
+    }
+
+    /// the RT side doesn't use the slot anymore
+    pub fn release(&mut self, index: usize) {
+        if index != ROOT.index && index < self.generations.len() {
+            self.free.push(index);
+        }
+    }
+}
+
+struct Slot<T> {
+    generation: u64,
+    value: Option<T>,
+}
+
+/// RT side : the nodes, by key
+pub struct Arena<T> {
+    slots: Vec<Slot<T>>,
+    len: usize,
+}
+
+impl<T> Arena<T> {
+    /// `capacity` empty slots
+    pub fn with_capacity(capacity: usize) -> Arena<T> {
+        let mut slots = Vec::with_capacity(capacity);
+        for _ in 0..capacity {
+            slots.push(Slot {
+                generation: 0,
+                value: None,
+            });
+        }
+        Arena { slots, len: 0 }
+    }
+
+    /// the value comes back if its slot is in use or past the capacity
+    pub fn insert(&mut self, key: Key, value: T) -> Result<(), T> {
+        let slot = match self.slots.get_mut(key.index) {
+            Some(slot) if slot.value.is_none() => slot,
+            _ => return Err(value),
+        };
+        slot.generation = key.generation;
+        slot.value = Some(value);
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn get(&self, key: Key) -> Option<&T> {
+        match self.slots.get(key.index) {
+            Some(slot) if slot.generation == key.generation => slot.value.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: Key) -> Option<&mut T> {
+        match self.slots.get_mut(key.index) {
+            Some(slot) if slot.generation == key.generation => slot.value.as_mut(),
+            _ => None,
+        }
+    }
+
+    pub fn remove(&mut self, key: Key) -> Option<T> {
+        match self.slots.get_mut(key.index) {
+            Some(slot) if slot.generation == key.generation => {
+                let value = slot.value.take();
+                if value.is_some() {
+                    self.len -= 1;
+                }
+                value
+            }
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().filter_map(|slot| slot.value.as_ref())
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.slots.iter_mut().filter_map(|slot| slot.value.as_mut())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generations() {
+        let mut keys = Keys::new();
+        let mut arena = Arena::with_capacity(4);
+        let a = keys.allocate().unwrap();
+        let b = keys.allocate().unwrap();
+        assert_ne!(a, ROOT);
+        arena.insert(a, "a").unwrap();
+        arena.insert(b, "b").unwrap();
+        assert_eq!(arena.insert(b, "b"), Err("b"));
+        assert_eq!((arena.get(a), arena.len()), (Some(&"a"), 2));
+
+        // the slot is reused, the old key doesn't work anymore
+        assert_eq!(arena.remove(a), Some("a"));
+        keys.release(a.index());
+        let c = keys.allocate().unwrap();
+        assert_eq!(c.index(), a.index());
+        arena.insert(c, "c").unwrap();
+        assert_eq!(arena.get(a), None);
+        assert_eq!(arena.remove(a), None);
+        assert_eq!(arena.get(c), Some(&"c"));
+        let mut values: Vec<&str> = arena.values().cloned().collect();
+        values.sort();
+        assert_eq!(values, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut keys = Keys::new();
+        let mut arena = Arena::with_capacity(2);
+        assert_eq!((arena.len(), arena.slots.len()), (0, 2));
+        arena.insert(ROOT, 0).unwrap();
+        arena.insert(keys.allocate().unwrap(), 1).unwrap();
+        assert_eq!(arena.slots.len(), 2);
+        // (no growing)
+        assert_eq!(arena.insert(keys.allocate().unwrap(), 2), Err(2));
+        assert_eq!((arena.len(), arena.slots.len()), (2, 2));
+
+        // keys for the slots only
+        let mut keys = Keys::with_capacity(2);
+        let a = keys.allocate().unwrap();
+        assert_eq!(keys.allocate(), None);
+        keys.release(a.index());
+        assert_eq!(keys.allocate().map(|k| k.index()), Some(a.index()));
+    }
+}
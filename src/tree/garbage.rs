@@ -0,0 +1,111 @@
+//! what the RT thread takes out of the tree goes back to the control side,
+//! to be dropped there : freeing memory can wait on the allocator.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, SyncSender, TrySendError};
+use std::thread;
+use synth::Synth;
+use efx::Efx;
+use params::ParamValue;
+use super::mixer::{AuxSend, Mixer, NodeId};
+use super::voices::Voice;
+use super::meter::MeterProbe;
+use super::sequencer::{Steps, Track};
+
+// (only held until dropped)
+#[allow(dead_code)]
+pub enum Freed {
+    /// the slot of a mixer in the arena ( see `Keys::release` )
+    Slot(usize),
+    Mixer(Mixer),
+    Voice(Voice),
+    Synth(Option<NodeId>, Box<dyn Synth>),
+    Efx(Option<NodeId>, Box<dyn Efx>),
+    Send(AuxSend),
+    Meter(MeterProbe),
+    Steps(Steps),
+    Track(Track),
+    /// a replaced parameter value ( see `MMTree::set_node_parameter` )
+    Parameter(NodeId, ParamValue),
+}
+
+/// RT side : what was removed and not sent yet, with its room reserved
+pub struct Garbage {
+    items: VecDeque<Freed>,
+}
+
+impl Garbage {
+    pub fn with_capacity(capacity: usize) -> Garbage {
+        Garbage { items: VecDeque::with_capacity(capacity) }
+    }
+
+    /// (the tree reserves room for all it holds, see `MMTree::has_room` :
+    /// were it full anyway, it grows rather than dropping here)
+    pub fn push(&mut self, item: Freed) {
+        self.items.push_back(item);
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    #[cfg(test)]
+    pub fn drain<'a>(&'a mut self) -> ::std::collections::vec_deque::Drain<'a, Freed> {
+        self.items.drain(..)
+    }
+
+    /// to the control side, in order. what `freed` can't take yet stays for
+    /// the next period.
+    pub fn send(&mut self, freed: &SyncSender<Freed>) {
+        while let Some(item) = self.items.pop_front() {
+            match freed.try_send(item) {
+                Ok(()) => {}
+                Err(TrySendError::Full(item)) |
+                Err(TrySendError::Disconnected(item)) => {
+                    self.items.push_front(item);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// control side : a thread dropping what comes from the RT thread. the
+/// mixer slots come out of the returned receiver.
+pub fn collect(freed: Receiver<Freed>) -> Receiver<usize> {
+    let (slots_tx, slots) = channel();
+    thread::spawn(move || for item in freed.iter() {
+        if let Freed::Slot(idx) = item {
+            if slots_tx.send(idx).is_err() {
+                break;
+            }
+        }
+    });
+    slots
+}
+
+/// pushes unless the room reserved is full ( the item comes back )
+pub fn push<T>(list: &mut Vec<T>, item: T) -> Result<(), T> {
+    if list.len() < list.capacity() {
+        list.push(item);
+        Ok(())
+    } else {
+        Err(item)
+    }
+}
+
+/// `retain` handing the removed items to `removed`, the others keep their order
+pub fn take_out<T, P, F>(list: &mut Vec<T>, is_removed: P, mut removed: F)
+where
+    P: Fn(&T) -> bool,
+    F: FnMut(T),
+{
+    let mut idx = 0;
+    while idx < list.len() {
+        if is_removed(&list[idx]) {
+            removed(list.remove(idx));
+        } else {
+            idx += 1;
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use traits::{SampleValue, SoundSample};
+use super::pbus::{link, Reader, Sender};
+
+/// peak and RMS levels of a mixer output ( see `MooMoot::meter` ).
+///
+/// updated by the RT thread at the end of each window (~ 50 ms), reading
+/// them never blocks it. dropping the meter stops the measure.
+pub struct Meter {
+    levels: Reader<(f64, f64)>,
+}
+
+impl Meter {
+    /// highest absolute sample value of the last window
+    pub fn peak(&self) -> f64 {
+        self.levels.value().0
+    }
+
+    /// root mean square of the last window
+    pub fn rms(&self) -> f64 {
+        self.levels.value().1
+    }
+
+    /// in dBFS ( -inf for silence )
+    pub fn peak_db(&self) -> f64 {
+        20. * self.peak().log10()
+    }
+
+    pub fn rms_db(&self) -> f64 {
+        20. * self.rms().log10()
+    }
+}
+
+/// RT side of a meter : fed with every frame of the mixer output
+pub struct MeterProbe {
+    window: usize,
+    count: usize,
+    peak: f64,
+    sum_sq: f64,
+    out: Sender<(f64, f64)>,
+    connected: bool,
+}
+
+/// a meter and its probe, measuring windows of `window` frames
+pub fn meter(window: usize) -> (Meter, MeterProbe) {
+    let (out, levels) = link((0., 0.));
+    (
+        Meter { levels },
+        MeterProbe {
+            window: window.max(1),
+            count: 0,
+            peak: 0.,
+            sum_sq: 0.,
+            out,
+            connected: true,
+        },
+    )
+}
+
+impl MeterProbe {
+    pub fn feed(&mut self, sample: &SoundSample) {
+        let (peak, sq) = match *sample {
+            SoundSample::Sample(SampleValue::Mono(x)) => (x.abs(), x * x),
+            SoundSample::Sample(SampleValue::Stereo(r, l)) => {
+                (r.abs().max(l.abs()), (r * r + l * l) / 2.)
+            }
+            _ => (0., 0.),
+        };
+        self.peak = self.peak.max(peak);
+        self.sum_sq += sq;
+        self.count += 1;
+
+        if self.count == self.window {
+            let rms = (self.sum_sq / self.window as f64).sqrt();
+            self.connected = self.out.send((self.peak, rms)).is_ok();
+            self.count = 0;
+            self.peak = 0.;
+            self.sum_sq = 0.;
+        }
+    }
+
+    /// false once the `Meter` is dropped
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use traits::{mono_value, stereo_value};
+
+    #[test]
+    fn test_meter() {
+        let (meter, mut probe) = meter(4);
+        for x in [0.5, -1., 0.5, 0.] {
+            probe.feed(&mono_value(x));
+        }
+        assert_eq!(meter.peak(), 1.);
+        assert_eq!(meter.rms(), 0.375f64.sqrt());
+        assert_eq!(meter.peak_db(), 0.);
+
+        // nothing new until the end of the window
+        probe.feed(&stereo_value(0.5, -0.5));
+        assert_eq!(meter.peak(), 1.);
+        for _ in 0..3 {
+            probe.feed(&SoundSample::Silence);
+        }
+        assert_eq!(meter.peak(), 0.5);
+        assert_eq!(meter.rms(), 0.25);
+
+        drop(meter);
+        for _ in 0..4 {
+            probe.feed(&SoundSample::Silence);
+        }
+        assert!(!probe.is_connected());
+    }
+}
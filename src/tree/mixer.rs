@@ -1,128 +1,405 @@
 use synth::Synth;
 use efx::Efx;
 use traits::SoundSample;
-use std::collections::LinkedList;
+use params::{ParamValue, Parameters};
+use snapshot::{MixerSnapshot, NodeSnapshot, SendSnapshot};
+use super::meter::MeterProbe;
+use super::voices::{self, Fade, Voice, VoiceLimit};
+use super::arena::Key;
+use super::mmtree::Capacities;
+use super::garbage::{push, take_out, Freed, Garbage};
+use std::time::Duration;
 type MixerId = String;
+/// synths and effects can have an id, to find them later (patches)
+pub type NodeId = String;
+// a node, with its id for the ones created from a patch
+pub type Node<T> = (Option<NodeId>, T);
 
-// These are actually owning tree nodes.
-// child lists are based on LinkedList because :
-// - we don't need individual access ( traversal only )
-// - we do need predictable node removal in the middle
-// - we need actual O(1) append ( Real time remember ?? )
+/// aux send : an amount of the mixer output (post effects) that goes to a
+/// return mixer.
+pub struct AuxSend {
+    /// index of the return in the tree's return list
+    pub to: usize,
+    pub amount: ParamValue,
+}
+
+// room reserved when a mixer is created ( on the control side ) : a full
+// mixer doesn't take more nodes, rather than growing in the RT thread.
+// ( see `Capacities` )
+pub const METERS: usize = 2;
+
+// These are actually owning tree nodes ( for the synths and effects : the
+// mixers themselves live in the tree's arena, linked by keys ).
+// the voices are in a Vec, oldest first ( see `voices::make_room` ) : done
+// ones are removed in place while sampling, and the capacity is kept.
 pub struct Mixer {
     is_transient: bool,
-    synths: LinkedList<Box<Synth>>,
-    pub sub_mixers: LinkedList<Box<Mixer>>,
-    effects: Vec<Box<Efx>>,
+    synths: Vec<Voice>,
+    voice_limit: Option<VoiceLimit>,
+    // fade out of removed synths and mixers, in frames
+    release: usize,
+    // removed : fading out
+    fade: Option<Fade>,
+    pub sub_mixers: Vec<Key>,
+    pub parent: Option<Key>,
+    effects: Vec<(Option<NodeId>, Box<dyn Efx>)>,
+    sends: Vec<AuxSend>,
+    meters: Vec<MeterProbe>,
+    // processing time, when profiled
+    cpu_time: Option<Duration>,
     pub id: MixerId,
 }
 
 impl Mixer {
+    #[cfg(test)]
     pub fn new(id: &str) -> Mixer {
+        Mixer::with_capacities(id, &Capacities::default())
+    }
+
+    pub fn with_capacities(id: &str, capacities: &Capacities) -> Mixer {
         Mixer {
             is_transient: false,
-            synths: LinkedList::new(),
-            effects: Vec::new(),
-            sub_mixers: LinkedList::new(),
+            synths: Vec::with_capacity(capacities.voices),
+            voice_limit: None,
+            release: 0,
+            fade: None,
+            effects: Vec::with_capacity(capacities.effects),
+            sends: Vec::with_capacity(capacities.sends),
+            meters: Vec::with_capacity(METERS),
+            cpu_time: None,
+            sub_mixers: Vec::with_capacity(capacities.sub_mixers),
+            parent: None,
             id: String::from(id),
         }
     }
 
+    #[cfg(test)]
     pub fn new_transient(id: &str) -> Mixer {
-        let mut mixr = Mixer::new(id);
-        mixr.is_transient = true;
-        mixr
+        Mixer::new(id).transient()
+    }
+
+    /// removed from the tree once silent ( see `MooMoot::add_transient_mixer` )
+    pub fn transient(mut self) -> Mixer {
+        self.is_transient = true;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn add_synth(&mut self, s: Box<dyn Synth>) {
+        self.add_synth_with_priority(s, 0).ok();
     }
 
-    pub fn add_synth(&mut self, s: Box<Synth>) {
-        self.synths.push_back(s);
+    // (when the mixer is full, the node comes back to be freed)
+    pub fn add_synth_with_priority(&mut self, s: Box<dyn Synth>, priority: i32) -> Result<(), Node<Box<dyn Synth>>> {
+        self.add_voice(None, s, priority)
     }
 
-    pub fn add_efx(&mut self, efx: Box<Efx>) {
-        self.effects.push(efx);
+    pub fn add_named_synth(&mut self, id: NodeId, s: Box<dyn Synth>) -> Result<(), Node<Box<dyn Synth>>> {
+        self.add_voice(Some(id), s, 0)
     }
 
-    pub fn add_sub_mixer(&mut self, mixer: Mixer) {
-        self.sub_mixers.push_back(Box::new(mixer));
+    // steals a voice first when over the voice limit
+    fn add_voice(&mut self, id: Option<NodeId>, s: Box<dyn Synth>, priority: i32) -> Result<(), Node<Box<dyn Synth>>> {
+        if self.synths.len() == self.synths.capacity() {
+            return Err((id, s));
+        }
+        if let Some(ref limit) = self.voice_limit {
+            voices::make_room(&mut self.synths, limit, 1);
+        }
+        let mut voice = Voice::new(id, s, priority);
+        voice.release = self.release;
+        self.synths.push(voice);
+        Ok(())
     }
 
-    pub fn mixer_count(&self) -> usize {
+    /// (steals the voices over the new limit right away)
+    pub fn set_voice_limit(&mut self, limit: Option<VoiceLimit>) {
+        self.voice_limit = limit;
+        if let Some(ref limit) = self.voice_limit {
+            voices::make_room(&mut self.synths, limit, 0);
+        }
+    }
 
-        let mut cnt = 1;
+    /// fade out length of the synths and of this mixer when they are
+    /// removed, stolen or done
+    pub fn set_release(&mut self, frames: usize) {
+        self.release = frames;
+        for voice in self.synths.iter_mut() {
+            voice.release = frames;
+        }
+    }
 
-        for mix in self.sub_mixers.iter() {
-            cnt += mix.mixer_count();
+    /// removed : done once faded out
+    pub fn fade_out(&mut self) {
+        if self.fade.is_none() {
+            self.fade = Some(Fade::new(self.release));
         }
+    }
 
-        cnt
+    pub fn is_fading(&self) -> bool {
+        self.fade.is_some()
     }
 
-    pub fn find_mixer(&mut self, id: &str) -> Option<&mut Mixer> {
+    pub fn add_efx(&mut self, efx: Box<dyn Efx>) -> Result<(), Node<Box<dyn Efx>>> {
+        push(&mut self.effects, (None, efx))
+    }
 
-        if id == self.id {
-            Some(self)
+    pub fn add_named_efx(&mut self, id: NodeId, efx: Box<dyn Efx>) -> Result<(), Node<Box<dyn Efx>>> {
+        push(&mut self.effects, (Some(id), efx))
+    }
+
+    pub fn add_meter(&mut self, meter: MeterProbe) -> Result<(), MeterProbe> {
+        push(&mut self.meters, meter)
+    }
+
+    pub fn add_send(&mut self, send: AuxSend) -> Result<(), AuxSend> {
+        push(&mut self.sends, send)
+    }
+
+    pub fn has_room_for_mixer(&self) -> bool {
+        self.sub_mixers.len() < self.sub_mixers.capacity()
+    }
+
+    // removes the sends to a return ( `to` index )
+    pub fn remove_send(&mut self, to: usize, garbage: &mut Garbage) {
+        take_out(&mut self.sends, |send| send.to == to, |send| garbage.push(Freed::Send(send)));
+    }
+
+    // a return was removed : forget the sends to it, and shift the
+    // indexes of the next ones.
+    pub fn return_removed(&mut self, idx: usize, garbage: &mut Garbage) {
+        self.remove_send(idx, garbage);
+        for send in self.sends.iter_mut() {
+            if send.to > idx {
+                send.to -= 1;
+            }
+        }
+    }
+
+    /// removes a synth or an effect. (returns false if there's none with
+    /// this id, eg. it has finished playing)
+    ///
+    /// synths fade out first ( see `set_release` ), effects are removed
+    /// right away.
+    pub fn remove_node(&mut self, id: &str, garbage: &mut Garbage) -> bool {
+        let is_node = |node_id: &Option<NodeId>| node_id.as_ref().map(|i| i.as_str()) == Some(id);
+        let nb_efx = self.effects.len();
+        take_out(
+            &mut self.effects,
+            |efx| is_node(&efx.0),
+            |(efx_id, efx)| garbage.push(Freed::Efx(efx_id, efx)),
+        );
+        let mut removed = self.effects.len() < nb_efx;
+
+        if self.release == 0 {
+            let nb_synths = self.synths.len();
+            take_out(&mut self.synths, |v| is_node(&v.id), |v| garbage.push(Freed::Voice(v)));
+            removed || self.synths.len() < nb_synths
         } else {
-            self.sub_mixers
-                .iter_mut()
-                .filter_map(|m| m.find_mixer(id))
-                .next()
+            for voice in self.synths.iter_mut() {
+                if is_node(&voice.id) && !voice.is_fading() {
+                    voice.release();
+                    removed = true;
+                }
+            }
+            removed
         }
     }
-}
 
+    /// the parameters of a synth or an effect
+    pub fn node_parameters(&mut self, id: &str) -> Option<&mut dyn Parameters> {
+        let is_node = |node_id: &Option<NodeId>| node_id.as_ref().map(|i| i.as_str()) == Some(id);
+        // (not a released one, a new node may have its id)
+        if let Some(voice) = self.synths
+            .iter_mut()
+            .find(|v| is_node(&v.id) && !v.is_fading())
+        {
+            return Some(voice.synth.get_parameters());
+        }
+        match self.effects.iter_mut().find(|n| is_node(&n.0)) {
+            Some(&mut (_, ref mut efx)) => Some(efx.get_parameters()),
+            None => None,
+        }
+    }
 
-// them solution for downcast ..
-trait AsSynth {
-    fn sample(&mut self) -> SoundSample;
-}
+    pub fn synth_count(&self) -> usize {
+        self.synths.len()
+    }
+
+    /// measure the processing time of this mixer (with what's below it)
+    pub fn set_profiled(&mut self, profiled: bool) {
+        self.cpu_time = if profiled {
+            Some(Duration::new(0, 0))
+        } else {
+            None
+        };
+    }
 
-impl AsSynth for Box<Synth> {
-    fn sample(&mut self) -> SoundSample {
-        self.as_mut().sample()
+    pub fn is_profiled(&self) -> bool {
+        self.cpu_time.is_some()
+    }
+
+    pub fn add_cpu_time(&mut self, time: Duration) {
+        if let Some(total) = self.cpu_time.as_mut() {
+            *total += time;
+        }
+    }
+
+    /// processing time since the last call, if profiled
+    pub fn take_cpu_time(&mut self) -> Option<Duration> {
+        let time = self.cpu_time;
+        if time.is_some() {
+            self.cpu_time = Some(Duration::new(0, 0));
+        }
+        time
+    }
+
+    /// `return_ids` : the return mixer ids, by index. (without the sub
+    /// mixers, the tree adds them)
+    pub fn snapshot(&mut self, return_ids: &[String]) -> MixerSnapshot {
+        MixerSnapshot {
+            id: self.id.clone(),
+            transient: self.is_transient,
+            synths: self.synths
+                .iter_mut()
+                .filter(|voice| !voice.is_fading())
+                .map(|voice| {
+                    let type_name = voice.synth.type_name().to_string();
+                    node_snapshot(&voice.id, type_name, voice.synth.get_parameters())
+                })
+                .collect(),
+            efx: self.effects
+                .iter_mut()
+                .map(|&mut (ref id, ref mut efx)| {
+                    let type_name = efx.type_name().to_string();
+                    node_snapshot(id, type_name, efx.get_parameters())
+                })
+                .collect(),
+            sends: self.sends
+                .iter()
+                .map(|send| {
+                    SendSnapshot {
+                        to: return_ids.get(send.to).cloned().unwrap_or_default(),
+                        amount: send.amount.peek(),
+                        source: send.amount.source(),
+                    }
+                })
+                .collect(),
+            mixers: Vec::new(),
+        }
     }
 }
 
-impl AsSynth for Box<Mixer> {
-    fn sample(&mut self) -> SoundSample {
-        self.as_mut().sample()
+
+fn node_snapshot(id: &Option<NodeId>, type_name: String, params: &mut dyn Parameters) -> NodeSnapshot {
+    let names: Vec<String> = params.descriptors().into_iter().map(|d| d.name.to_string()).collect();
+    let values = params.map_parameters();
+    NodeSnapshot {
+        id: id.clone(),
+        type_name,
+        params: names.iter().cloned().zip(values.iter().map(|p| p.peek())).collect(),
+        sources: names
+            .into_iter()
+            .zip(values.iter().map(|p| p.source()))
+            .filter_map(|(name, source)| source.map(|s| (name, s)))
+            .collect(),
     }
 }
 
-// UGLY : see also drain_filter in nightly
-fn sample_and_remove<S: AsSynth>(synths: &mut LinkedList<S>) -> SoundSample {
+// the done voices go to the garbage, the others keep their order
+fn sample_and_remove(synths: &mut Vec<Voice>, garbage: &mut Garbage) -> SoundSample {
 
     let mut res = SoundSample::Silence;
+    let mut kept = 0;
 
-    synths.drain_filter(|s| {
-        let sample = s.sample();
-        match sample {
-            SoundSample::Done => true,
-            _ => {
+    for idx in 0..synths.len() {
+        match synths[idx].sample() {
+            SoundSample::Done => {}
+            sample => {
                 res += sample;
-                false
+                synths.swap(kept, idx);
+                kept += 1;
             }
         }
-    });
+    }
+    while synths.len() > kept {
+        if let Some(voice) = synths.pop() {
+            garbage.push(Freed::Voice(voice));
+        }
+    }
     res
 }
 
 impl Mixer {
+    // a mixer that doesn't send anywhere
+    #[cfg(test)]
     pub fn sample(&mut self) -> SoundSample {
+        self.process(SoundSample::Silence, &mut [], &mut Garbage::with_capacity(0))
+    }
 
-        let res = sample_and_remove(&mut self.synths) + sample_and_remove(&mut self.sub_mixers);
+    /// mix `input` ( the sub mixers ) with the mixer synths, then apply
+    /// effects.
+    ///
+    /// the result is also sent to the aux `returns` inputs (indexed by
+    /// `AuxSend::to`). what's done goes to the `garbage`.
+    pub fn process(
+        &mut self,
+        input: SoundSample,
+        returns: &mut [SoundSample],
+        garbage: &mut Garbage,
+    ) -> SoundSample {
+        // removed : fading out, sends included
+        let gain = match self.fade.as_mut().map(|fade| fade.next()) {
+            Some(None) => return SoundSample::Done,
+            Some(Some(gain)) => gain,
+            None => 1.,
+        };
+        let out = self.mix(input, returns, gain, garbage);
+        if !self.meters.is_empty() {
+            for meter in self.meters.iter_mut() {
+                meter.feed(&out);
+            }
+            take_out(&mut self.meters, |m| !m.is_connected(), |m| garbage.push(Freed::Meter(m)));
+        }
+        out
+    }
+
+    fn mix(
+        &mut self,
+        input: SoundSample,
+        returns: &mut [SoundSample],
+        gain: f64,
+        garbage: &mut Garbage,
+    ) -> SoundSample {
+
+        let res = input + sample_and_remove(&mut self.synths, garbage);
 
         if let SoundSample::Sample(value) = res {
             let mut sample = value;
-            for e in self.effects.iter_mut() {
+            for &mut (_, ref mut e) in self.effects.iter_mut() {
                 if let SoundSample::Sample(processed) = e.sample(sample) {
                     sample = processed;
                 } else {
                     return SoundSample::Done;
                 }
             }
+            if gain < 1. {
+                sample = sample * gain;
+            }
+
+            for send in self.sends.iter() {
+                if let Some(ret) = returns.get_mut(send.to) {
+                    *ret += SoundSample::Sample(sample.clone() * send.amount.value());
+                }
+            }
 
             return SoundSample::Sample(sample);
         }
+        // (silent : the amounts still move on, smoothed and formula amounts
+        // don't jump when the sound comes back)
+        for send in self.sends.iter() {
+            send.amount.value();
+        }
         if self.is_transient {
             SoundSample::Done
         } else {
@@ -1,86 +1,615 @@
 
-use tree::mixer::Mixer;
+use tree::mixer::{Mixer, AuxSend, NodeId};
 use traits::*;
-use super::pbus::BusSystem;
+use params::ParamValue;
+use super::pbus::{Bus, BusSystem};
 use synth::Synth;
 use efx::Efx;
-use uuid::Uuid;
+use std::mem;
+use std::time::{Duration, Instant};
+use snapshot::{MixerSnapshot, TreeSnapshot};
+use super::meter::MeterProbe;
+use super::voices::VoiceLimit;
+use super::arena::{Arena, Key, ROOT};
+use super::garbage::{push, take_out, Freed, Garbage};
+use std::sync::mpsc::SyncSender;
+use super::{mixer, sequencer};
 
+const OUTPUT_METERS: usize = 4;
+// removed nodes waiting for the control side, on top of what the tree holds :
+// past it, the commands wait for the next period ( see `has_room` )
+const GARBAGE: usize = 1024;
+// at most what one command brings to the garbage
+const COMMAND: usize = 4;
+
+/// the room reserved up front ( see `MooMoot::start_with` ) : the RT thread
+/// doesn't allocate, a full list refuses new nodes ( see
+/// `MooMoot::failed_commands` )
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capacities {
+    /// in the whole tree, the root and the returns included
+    pub mixers: usize,
+    pub returns: usize,
+    /// per mixer
+    pub sub_mixers: usize,
+    /// per mixer ( `MooMoot::set_voice_limit` steals voices before that )
+    pub voices: usize,
+    pub effects: usize,
+    pub sends: usize,
+}
+
+impl Default for Capacities {
+    fn default() -> Capacities {
+        Capacities {
+            mixers: 256,
+            returns: 16,
+            sub_mixers: 16,
+            voices: 64,
+            effects: 8,
+            sends: 8,
+        }
+    }
+}
+
+impl Capacities {
+    // what the tree can hand to the garbage at once : all that it holds
+    fn nodes(&self) -> usize {
+        self.mixers * (2 + self.voices + self.effects + self.sends + mixer::METERS) +
+            OUTPUT_METERS + 2 * sequencer::TRACKS
+    }
+}
+
+// the mixers are in an arena : commands find them in constant time by key
+// ( see `arena` ), and the tree is linked with keys ( `Mixer::sub_mixers`
+// and `Mixer::parent` ).
+//
+// the lists don't grow in the RT thread : the mixers are built on the control
+// side ( `insert_mixer` ), every list has its room reserved and a full one
+// refuses new nodes. removed nodes go back to the control side to be dropped
+// ( see `garbage` ), the garbage has room for everything the tree holds.
+// nodes come with their parameters connected to the buses, and bus values
+// with a `Bus` handle : the names are resolved on the control side.
 pub struct MMTree {
-    root_mixer: Box<Mixer>,
+    mixers: Arena<Mixer>,
+    // return mixers live beside the root : they are processed after it,
+    // once all the sends have been summed in `return_inputs`.
+    returns: Vec<Key>,
+    return_inputs: Vec<SoundSample>,
     buses: BusSystem,
+    // on the main output
+    meters: Vec<MeterProbe>,
+    // profiling the top level mixers : frames since the last report
+    profiled_frames: Option<usize>,
+    // fade out of removed nodes, in frames
+    release: usize,
+    // the audio clock stands still : bus ramps wait ( see `hold_clock` )
+    clock_held: bool,
+    // for the control side : removed nodes, slots of the dropped mixers
+    garbage: Garbage,
+}
+
+// a mixer being removed ( fading out ) is not there anymore
+fn live(mixers: &mut Arena<Mixer>, key: Key) -> Option<&mut Mixer> {
+    match mixers.get_mut(key) {
+        Some(mxr) => if mxr.is_fading() { None } else { Some(mxr) },
+        None => None,
+    }
 }
 
 
 impl MMTree {
+    #[cfg(test)]
     pub fn new() -> MMTree {
+        MMTree::with_capacities(&Capacities::default())
+    }
+
+    pub fn with_capacities(capacities: &Capacities) -> MMTree {
+        let mut mixers = Arena::with_capacity(capacities.mixers);
+        // (the arena is empty)
+        mixers.insert(ROOT, Mixer::with_capacities("root", capacities)).ok();
         MMTree {
-            root_mixer: Box::new(Mixer::new("root")),
+            mixers,
+            returns: Vec::with_capacity(capacities.returns),
+            return_inputs: Vec::with_capacity(capacities.returns),
             buses: BusSystem::new(),
+            meters: Vec::with_capacity(OUTPUT_METERS),
+            profiled_frames: None,
+            release: 0,
+            clock_held: false,
+            garbage: Garbage::with_capacity(capacities.nodes() + GARBAGE),
+        }
+    }
+
+
+    fn find_mixer(&mut self, key: Key) -> Option<&mut Mixer> {
+        live(&mut self.mixers, key)
+    }
+
+    // a refused node goes to the garbage
+    fn refused(&mut self, res: Result<(), (Freed, &'static str)>) -> Result<(), &'static str> {
+        res.map_err(|(freed, err)| {
+            self.garbage.push(freed);
+            err
+        })
+    }
+
+    fn link_mixer(&mut self, parent: Option<Key>, key: Key, mut mixer: Mixer) -> Result<(), &'static str> {
+        mixer.set_release(self.release);
+        mixer.set_profiled(self.profiled_frames.is_some() && parent.is_none_or(|p| p == ROOT));
+        mixer.parent = parent;
+        if let Err(mixer) = self.mixers.insert(key, mixer) {
+            // (past the capacity : the key is lost, there's no slot to give
+            // back. in use : can't be, the control side gives unused keys)
+            self.garbage.push(Freed::Mixer(mixer));
+            return Err("no free slot for the mixer");
+        }
+        if let Some(parent) = parent {
+            let linked = match self.find_mixer(parent) {
+                Some(mxr) => push(&mut mxr.sub_mixers, key).is_ok(),
+                None => false,
+            };
+            if !linked {
+                // the key won't be used : the mixer and its slot go back to
+                // the control side
+                self.drop_mixer(key);
+                return Err("can't find parent mixer, or it is full");
+            }
         }
+        Ok(())
     }
 
+    // a mixer built beforehand ( no allocation here ). its id is for
+    // snapshots and profiling
+    pub fn insert_mixer(&mut self, parent: Key, key: Key, mixer: Mixer) -> Result<(), &str> {
+        self.link_mixer(Some(parent), key, mixer)
+    }
+
+    // "return" mixer : it sums what other mixers send to it, and outputs
+    // directly to the main output.
+    pub fn insert_return(&mut self, key: Key, mixer: Mixer) -> Result<(), &str> {
+        if self.returns.len() == self.returns.capacity() {
+            self.garbage.push(Freed::Mixer(mixer));
+            self.garbage.push(Freed::Slot(key.index()));
+            return Err("too many return mixers");
+        }
+        self.link_mixer(None, key, mixer)?;
+        self.returns.push(key);
+        self.return_inputs.push(SoundSample::Silence);
+        Ok(())
+    }
+
+    // send `amount` of mixer output to the return mixer.
+    pub fn add_send(&mut self, mixer: Key, return_key: Key, amount: ParamValue) -> Result<(), &str> {
+
+        if mixer == return_key {
+            return Err("can't send a return mixer to itself");
+        }
+
+        let to = match self.returns.iter().position(|&r| r == return_key) {
+            Some(idx) => idx,
+            None => return Err("can't find return mixer"),
+        };
 
-    // "transient" mixer, removed when AsMut
-    pub fn add_transient_mixer(&mut self, parent: &str) -> Result<String, &str> {
-        if let Some(mxr) = self.root_mixer.find_mixer(parent) {
-            let uuid = Uuid::new_v4(); //Uuid::new_v4();
-            let mixer_id = uuid.simple().to_string();
-            mxr.add_sub_mixer(Mixer::new_transient(&mixer_id));
-            Ok(mixer_id)
+        let res = match live(&mut self.mixers, mixer) {
+            Some(mxr) => mxr.add_send(AuxSend { to, amount }).map_err(|send| (Freed::Send(send), "mixer is full")),
+            None => Err((Freed::Send(AuxSend { to, amount }), "can't find parent mixer")),
+        };
+        self.refused(res)
+    }
+
+    // takes a Box, as Synth is a trait.
+    pub fn add_synth(&mut self, mixer: Key, synth: Box<dyn Synth>) -> Result<(), &str> {
+        self.add_synth_with_priority(mixer, synth, 0)
+    }
+
+    // the priority is for voice stealing ( see `set_voice_limit` )
+    pub fn add_synth_with_priority(
+        &mut self,
+        mixer: Key,
+        synth: Box<dyn Synth>,
+        priority: i32,
+    ) -> Result<(), &str> {
+
+        let res = match self.find_mixer(mixer) {
+            Some(mxr) => mxr.add_synth_with_priority(synth, priority).map_err(|(id, s)| (Freed::Synth(id, s), "mixer is full")),
+            None => Err((Freed::Synth(None, synth), "can't find parent mixer")),
+        };
+        self.refused(res)
+    }
+
+    // a synth with an id ( see `remove_node` and `set_node_parameter` )
+    pub fn add_named_synth(&mut self, mixer: Key, id: NodeId, synth: Box<dyn Synth>) -> Result<(), &str> {
+
+        let res = match self.find_mixer(mixer) {
+            Some(mxr) => mxr.add_named_synth(id, synth).map_err(|(id, s)| (Freed::Synth(id, s), "mixer is full")),
+            None => Err((Freed::Synth(Some(id), synth), "can't find parent mixer")),
+        };
+        self.refused(res)
+    }
+
+    pub fn add_named_efx(&mut self, mixer: Key, id: NodeId, fx: Box<dyn Efx>) -> Result<(), &str> {
+
+        let res = match self.find_mixer(mixer) {
+            Some(mxr) => mxr.add_named_efx(id, fx).map_err(|(id, fx)| (Freed::Efx(id, fx), "mixer is full")),
+            None => Err((Freed::Efx(Some(id), fx), "can't find parent mixer")),
+        };
+        self.refused(res)
+    }
+
+    // removed, stolen or done synths, and removed mixers, fade out in
+    // `frames` instead of stopping dead. (0 : removed right away)
+    pub fn set_release(&mut self, frames: usize) {
+        self.release = frames;
+        for mixer in self.mixers.values_mut() {
+            mixer.set_release(frames);
+        }
+    }
+
+    pub fn set_voice_limit(&mut self, mixer: Key, limit: Option<VoiceLimit>) -> Result<(), &str> {
+        if let Some(mxr) = self.find_mixer(mixer) {
+            mxr.set_voice_limit(limit);
+            Ok(())
         } else {
             Err("can't find parent mixer")
         }
     }
 
-    // "named" mixer. It'll stay there. CANNOT have a non named parent
-    pub fn add_mixer(&mut self, parent: &str, mixer_id: &str) -> Result<(), &str> {
-
-        if let Some(mxr) = self.root_mixer.find_mixer(parent) {
-            mxr.add_sub_mixer(Mixer::new(mixer_id));
+    // a synth that is done playing is not there anymore : that's fine.
+    pub fn remove_node(&mut self, mixer: Key, id: &str) -> Result<(), &str> {
+        if let Some(mxr) = live(&mut self.mixers, mixer) {
+            mxr.remove_node(id, &mut self.garbage);
             Ok(())
         } else {
             Err("can't find parent mixer")
         }
     }
 
-    // takes a Box, as Synth is a trait.
-    pub fn add_synth(&mut self, mixer_id: &str, mut synth: Box<Synth>) -> Result<(), &str> {
+    // (same, the node may be gone)
+    /// `idx` : the parameter index in the node descriptors, looked up on
+    /// the control side. the replaced value goes to the garbage.
+    pub fn set_node_parameter(
+        &mut self,
+        mixer: Key,
+        id: NodeId,
+        idx: usize,
+        value: ParamValue,
+    ) -> Result<(), &str> {
+
+        let (freed, res) = match self.find_mixer(mixer) {
+            Some(mxr) => {
+                match mxr.node_parameters(&id).map(|params| params.parameter(idx)) {
+                    Some(Some(param)) => (mem::replace(param, value), Ok(())),
+                    Some(None) => (value, Err("invalid parameter")),
+                    // already gone : fine
+                    None => (value, Ok(())),
+                }
+            }
+            None => (value, Err("can't find parent mixer")),
+        };
+        self.free(Freed::Parameter(id, freed));
+        res
+    }
+
+    // a mixer with all its content ( after fading out ), or a return.
+    pub fn remove_mixer(&mut self, key: Key) -> Result<(), &str> {
+        if key == ROOT {
+            return Err("can't remove the root mixer");
+        }
+        if let Some(idx) = self.returns.iter().position(|&r| r == key) {
+            self.returns.remove(idx);
+            self.return_inputs.remove(idx);
+            for mixer in self.mixers.values_mut() {
+                mixer.return_removed(idx, &mut self.garbage);
+            }
+            self.drop_mixer(key);
+            return Ok(());
+        }
+
+        let release = self.release;
+        match self.mixers.get_mut(key) {
+            // already on its way out : fine
+            Some(mxr) if mxr.is_fading() => return Ok(()),
+            Some(mxr) => {
+                if release > 0 {
+                    mxr.fade_out();
+                    return Ok(());
+                }
+            }
+            None => return Err("can't find mixer"),
+        }
+        self.unlink(key);
+        self.drop_mixer(key);
+        Ok(())
+    }
+
+    // out of its parent's sub mixers
+    fn unlink(&mut self, key: Key) {
+        let parent = self.mixers.get(key).and_then(|m| m.parent);
+        if let Some(parent) = parent.and_then(|p| self.mixers.get_mut(p)) {
+            parent.sub_mixers.retain(|&k| k != key);
+        }
+    }
 
-        synth.as_mut().connect_parameters(&mut self.buses);
+    // out of the arena, with its sub mixers ( already unlinked ), to the
+    // garbage
+    fn drop_mixer(&mut self, key: Key) {
+        if let Some(mixer) = self.mixers.remove(key) {
+            for &sub in mixer.sub_mixers.iter() {
+                self.drop_mixer(sub);
+            }
+            self.garbage.push(Freed::Mixer(mixer));
+            self.garbage.push(Freed::Slot(key.index()));
+        }
+    }
+
+    // for the garbage of the sequencer
+    pub fn free(&mut self, item: Freed) {
+        self.garbage.push(item);
+    }
+
+    // what was removed since the last call, to drop on the control side
+    #[cfg(test)]
+    pub fn freed<'a>(&'a mut self) -> ::std::collections::vec_deque::Drain<'a, Freed> {
+        self.garbage.drain()
+    }
+
+    // same as `freed`, what the channel can't take yet stays for the next
+    // period
+    pub fn send_freed(&mut self, freed: &SyncSender<Freed>) {
+        self.garbage.send(freed);
+    }
+
+    /// can take another command : what it brings to the garbage fits, along
+    /// with everything in the tree. ( otherwise the commands wait for the
+    /// control side to take the garbage )
+    pub fn has_room(&self) -> bool {
+        self.garbage.len() + COMMAND <= GARBAGE
+    }
+
+    // relinks a mixer with its subtree (synths, effects, sends, meters) under
+    // another parent, in O(depth). returns can't be moved.
+    pub fn move_mixer(&mut self, key: Key, new_parent: Key) -> Result<(), &str> {
+        if key == ROOT {
+            return Err("can't move the root mixer");
+        }
+        if self.returns.contains(&key) {
+            return Err("can't move a return mixer");
+        }
+        if self.find_mixer(key).is_none() {
+            return Err("can't find mixer");
+        }
+        match self.find_mixer(new_parent) {
+            Some(parent) if !parent.has_room_for_mixer() => return Err("parent mixer is full"),
+            Some(_) => {}
+            None => return Err("can't find parent mixer"),
+        }
+        // the new parent can't be below the mixer
+        let mut ancestor = Some(new_parent);
+        while let Some(a) = ancestor {
+            if a == key {
+                return Err("can't move a mixer below itself");
+            }
+            ancestor = self.mixers.get(a).and_then(|m| m.parent);
+        }
 
-        if let Some(mxr) = self.root_mixer.find_mixer(mixer_id) {
-            mxr.add_synth(synth);
+        self.unlink(key);
+        let profiled = self.profiled_frames.is_some() && new_parent == ROOT;
+        if let Some(mixer) = self.mixers.get_mut(key) {
+            mixer.parent = Some(new_parent);
+            mixer.set_profiled(profiled);
+        }
+        if let Some(parent) = self.mixers.get_mut(new_parent) {
+            // (room checked above)
+            push(&mut parent.sub_mixers, key).ok();
+        }
+        Ok(())
+    }
+
+    pub fn remove_send(&mut self, mixer: Key, return_key: Key) -> Result<(), &str> {
+        let to = match self.returns.iter().position(|&r| r == return_key) {
+            Some(idx) => idx,
+            None => return Err("can't find return mixer"),
+        };
+
+        if let Some(mxr) = live(&mut self.mixers, mixer) {
+            mxr.remove_send(to, &mut self.garbage);
             Ok(())
         } else {
             Err("can't find parent mixer")
         }
     }
 
+    // levels of a mixer output (after its effects)
+    pub fn add_meter(&mut self, mixer: Key, meter: MeterProbe) -> Result<(), &str> {
+        let res = match self.find_mixer(mixer) {
+            Some(mxr) => mxr.add_meter(meter).map_err(|m| (Freed::Meter(m), "mixer is full")),
+            None => Err((Freed::Meter(meter), "can't find mixer")),
+        };
+        self.refused(res)
+    }
+
+    // levels of the main output (root and returns)
+    pub fn add_output_meter(&mut self, meter: MeterProbe) -> Result<(), &str> {
+        let res = push(&mut self.meters, meter).map_err(|m| (Freed::Meter(m), "too many output meters"));
+        self.refused(res)
+    }
+
+    // the buses of a tree used on its own : nodes are connected to them, and
+    // values set by name
+    #[cfg(test)]
+    pub fn buses(&mut self) -> &mut BusSystem {
+        &mut self.buses
+    }
+
+    #[cfg(test)]
     pub fn set_bus_value(&mut self, bus: &str, value: f64) -> Result<(), &str> {
         self.buses.publish(bus, value).map_err(
             |_| "no such channel",
         )
     }
 
-    pub fn add_efx(&mut self, mixer_id: &str, mut fx: Box<Efx>) -> Result<(), &str> {
+    pub fn set_bus(&mut self, bus: &Bus<f64>, value: f64) {
+        self.buses.set(bus, value);
+    }
 
-        fx.as_mut().connect_parameters(&mut self.buses);
+    // linear ramp to `value`, one step per sample
+    pub fn ramp_bus(&mut self, bus: Bus<f64>, value: f64, frames: usize) -> Result<(), &str> {
+        self.buses.ramp_bus(bus, value, frames).map_err(|_| "too many bus ramps")
+    }
 
-        if let Some(mxr) = self.root_mixer.find_mixer(mixer_id) {
-            mxr.add_efx(fx);
-            Ok(())
-        } else {
-            Err("can't find parent mixer")
+    pub fn add_efx(&mut self, mixer: Key, fx: Box<dyn Efx>) -> Result<(), &str> {
+
+        let res = match self.find_mixer(mixer) {
+            Some(mxr) => mxr.add_efx(fx).map_err(|(id, fx)| (Freed::Efx(id, fx), "mixer is full")),
+            None => Err((Freed::Efx(None, fx), "can't find parent mixer")),
+        };
+        self.refused(res)
+    }
+
+
+    pub fn snapshot(&mut self) -> TreeSnapshot {
+        let return_ids: Vec<String> = self.returns
+            .iter()
+            .filter_map(|&r| self.mixers.get(r).map(|m| m.id.clone()))
+            .collect();
+        let returns = self.returns.clone();
+        TreeSnapshot {
+            root: self.mixer_snapshot(ROOT, &return_ids).unwrap(),
+            returns: returns
+                .into_iter()
+                .filter_map(|r| self.mixer_snapshot(r, &return_ids))
+                .collect(),
+            // ( none in the RT thread, they are named on the control side )
+            buses: self.buses.snapshot(),
+        }
+    }
+
+    fn mixer_snapshot(&mut self, key: Key, return_ids: &[String]) -> Option<MixerSnapshot> {
+        let (mut snapshot, sub_mixers) = match self.find_mixer(key) {
+            Some(mxr) => (mxr.snapshot(return_ids), mxr.sub_mixers.clone()),
+            None => return None,
+        };
+        snapshot.mixers = sub_mixers
+            .into_iter()
+            .filter_map(|sub| self.mixer_snapshot(sub, return_ids))
+            .collect();
+        Some(snapshot)
+    }
+
+    pub fn synth_count(&self) -> usize {
+        self.mixers.values().map(|m| m.synth_count()).sum()
+    }
+
+    // the mixers under the root, and the returns
+    fn top_level_mixers(&self) -> Vec<Key> {
+        let root = self.mixers.get(ROOT).map_or(Vec::new(), |r| r.sub_mixers.clone());
+        root.into_iter().chain(self.returns.iter().cloned()).collect()
+    }
+
+    // measure the processing time of the mixers under the root, and of the
+    // returns ( see `take_mixer_times` )
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiled_frames = if profiling { Some(0) } else { None };
+        for key in self.top_level_mixers() {
+            if let Some(mixer) = self.mixers.get_mut(key) {
+                mixer.set_profiled(profiling);
+            }
         }
     }
 
+    // (frames, processing time of each top level mixer) since the last call
+    pub fn take_mixer_times(&mut self) -> (usize, Vec<(String, Duration)>) {
+        let frames = self.profiled_frames.unwrap_or(0);
+        if self.profiled_frames.is_some() {
+            self.profiled_frames = Some(0);
+        }
+        let mut times = Vec::new();
+        for key in self.top_level_mixers() {
+            if let Some(mixer) = self.mixers.get_mut(key) {
+                if let Some(time) = mixer.take_cpu_time() {
+                    times.push((mixer.id.clone(), time));
+                }
+            }
+        }
+        (frames, times)
+    }
 
     pub fn mixer_count(&self) -> usize {
-        return self.root_mixer.mixer_count();
+        self.mixers.len()
+    }
+
+    // a mixer with its sub mixers ( depth first ). `Done` : it can go.
+    fn process_mixer(&mut self, key: Key, input: SoundSample) -> SoundSample {
+        let (mut sub_mixers, start) = match self.mixers.get_mut(key) {
+            Some(mxr) => {
+                let start = if mxr.is_profiled() {
+                    Some(Instant::now())
+                } else {
+                    None
+                };
+                // (taken out while they are processed : no allocation)
+                (std::mem::take(&mut mxr.sub_mixers), start)
+            }
+            None => return SoundSample::Done,
+        };
+
+        let mut sum = input;
+        let mut idx = 0;
+        while idx < sub_mixers.len() {
+            match self.process_mixer(sub_mixers[idx], SoundSample::Silence) {
+                SoundSample::Done => {
+                    let sub = sub_mixers.remove(idx);
+                    self.drop_mixer(sub);
+                }
+                sample => {
+                    sum += sample;
+                    idx += 1;
+                }
+            }
+        }
+
+        match self.mixers.get_mut(key) {
+            Some(mxr) => {
+                mxr.sub_mixers = sub_mixers;
+                let out = mxr.process(sum, &mut self.return_inputs, &mut self.garbage);
+                if let Some(start) = start {
+                    mxr.add_cpu_time(start.elapsed());
+                }
+                out
+            }
+            None => SoundSample::Done,
+        }
+    }
+
+    /// bus ramps stand still while the clock is held ( following a stopped
+    /// transport )
+    pub fn hold_clock(&mut self, held: bool) {
+        self.clock_held = held;
     }
 
     pub fn sample(&mut self) -> SoundSample {
-        return self.root_mixer.sample();
+        if !self.clock_held {
+            self.buses.tick();
+        }
+        if let Some(frames) = self.profiled_frames.as_mut() {
+            *frames += 1;
+        }
+
+        let mut out = self.process_mixer(ROOT, SoundSample::Silence);
+
+        // a return sending to a previous one is heard on the next frame.
+        for idx in 0..self.returns.len() {
+            let input = mem::replace(&mut self.return_inputs[idx], SoundSample::Silence);
+            out += self.process_mixer(self.returns[idx], input);
+        }
+
+        if !self.meters.is_empty() {
+            for meter in self.meters.iter_mut() {
+                meter.feed(&out);
+            }
+            let garbage = &mut self.garbage;
+            take_out(&mut self.meters, |m| !m.is_connected(), |m| garbage.push(Freed::Meter(m)));
+        }
+        out
     }
 }
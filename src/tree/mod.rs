@@ -1,6 +1,11 @@
+pub mod arena;
+pub mod garbage;
+pub mod meter;
 pub mod mmtree;
-mod mixer;
+pub mod mixer;
 pub mod pbus;
+pub mod sequencer;
+pub mod voices;
 
 #[cfg(test)]
 mod tests;
@@ -1,8 +1,17 @@
-use super::sender::{Sender, Reader, link};
+use std::sync::Arc;
+use super::sender::{Reader, Shared};
 
+/// a value and its readers. made on the control side : the handles are
+/// cheap to clone and subscribing doesn't allocate, the RT thread only
+/// publishes.
 pub struct Bus<T> {
-    senders: Vec<Sender<T>>,
-    initial_value: T,
+    cell: Arc<Shared<T>>,
+}
+
+impl<T> Clone for Bus<T> {
+    fn clone(&self) -> Bus<T> {
+        Bus { cell: self.cell.clone() }
+    }
 }
 
 impl<T> Bus<T>
@@ -10,25 +19,30 @@ where
     T: Copy,
 {
     pub fn new(initial_value: T) -> Bus<T> {
-        Bus {
-            senders: Vec::new(),
-            initial_value: initial_value,
-        }
+        Bus { cell: Arc::new(Shared::new(initial_value)) }
+    }
+
+    pub fn subscribe(&self) -> Reader<T> {
+        self.cell.reader()
     }
 
-    pub fn subscribe(&mut self) -> Reader<T> {
-        let (send, reader) = link::<T>(self.initial_value);
-        self.senders.push(send);
-        reader
+    pub fn publish(&self, value: T) {
+        self.cell.set(value);
     }
 
-    pub fn publish(&mut self, value: T) {
-        self.initial_value = value;
-        self.senders.retain(|sender| sender.send(value).is_ok())
+    /// last published value
+    pub fn value(&self) -> T {
+        self.cell.value()
     }
 
+    /// readers still connected
     pub fn sub_count(&self) -> usize {
-        self.senders.len()
+        self.cell.readers()
+    }
+
+    /// handles of the same bus ?
+    pub fn is(&self, other: &Bus<T>) -> bool {
+        Arc::ptr_eq(&self.cell, &other.cell)
     }
 }
 
@@ -38,7 +52,7 @@ mod tests {
 
     #[test]
     fn test_bus() {
-        let mut bus = Bus::new(42.0);
+        let bus = Bus::new(42.0);
 
         let r1 = bus.subscribe();
 
@@ -51,8 +65,13 @@ mod tests {
             assert_eq!(1.0, r2.value());
             assert_eq!(2, bus.sub_count());
         }
-
-        bus.publish(2.); // need to propagate a value to update subcount
         assert_eq!(1, bus.sub_count());
+
+        // handles aren't readers
+        let handle = bus.clone();
+        assert!(handle.is(&bus));
+        assert!(!handle.is(&Bus::new(1.0)));
+        handle.publish(2.);
+        assert_eq!((2.0, 1), (r1.value(), bus.sub_count()));
     }
 }
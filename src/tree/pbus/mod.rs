@@ -4,12 +4,15 @@ mod bus;
 use std::error;
 use std::fmt;
 use std::collections::HashMap;
+use snapshot::BusSnapshot;
 
-pub use self::sender::Reader;
+pub use self::sender::{link, Reader, Sender};
+pub use self::bus::Bus;
 
 #[derive(Debug)]
 pub enum BusError {
     NoSuchChannel(String),
+    TooManyRamps,
 }
 
 impl fmt::Display for BusError {
@@ -20,6 +23,7 @@ impl fmt::Display for BusError {
                     .field("channel", &chan)
                     .finish()
             }
+            BusError::TooManyRamps => fmt.write_str("TooManyRamps"),
         }
     }
 }
@@ -28,32 +32,124 @@ impl error::Error for BusError {
     fn description(&self) -> &str {
         match *self {
             BusError::NoSuchChannel(_) => "No such channel",
+            BusError::TooManyRamps => "Too many ramps running",
         }
     }
 }
+// ramps running at once : room reserved, the RT thread doesn't allocate
+const RAMPS: usize = 32;
+
+// a bus going linearly to a value, one step per frame
+struct BusRamp {
+    bus: Bus<f64>,
+    from: f64,
+    to: f64,
+    pos: usize,
+    len: usize,
+}
+
+/// the buses by name, and the ramps running on them.
+///
+/// names are resolved on the control side ( `sub`, `bus` ) : the RT thread
+/// is only given `Bus` handles, for `set` and `ramp_bus`.
 pub struct BusSystem {
-    busses: HashMap<String, bus::Bus<f64>>,
+    names: HashMap<String, Bus<f64>>,
+    ramps: Vec<BusRamp>,
 }
 
 impl BusSystem {
     // ok, there's a big fat leak : when there are no listenner to a bus, it stays in the map.
     pub fn new() -> BusSystem {
-        BusSystem { busses: HashMap::new() }
+        BusSystem {
+            names: HashMap::new(),
+            ramps: Vec::with_capacity(RAMPS),
+        }
     }
 
     // ideally sub<T> -> Receiver<T>
     pub fn sub(&mut self, chan: &str) -> Reader<f64> {
+        self.resolve(chan).subscribe()
+    }
+
+    /// handle of a bus for `set` and `ramp_bus`, the bus is made if needed
+    /// ( like `sub` )
+    pub fn bus(&mut self, chan: &str) -> Bus<f64> {
+        self.resolve(chan).clone()
+    }
+
+    fn resolve(&mut self, chan: &str) -> &Bus<f64> {
+        self.names.entry(chan.to_string()).or_insert_with(|| Bus::new(0.0))
+    }
 
-        self.busses
-            .entry(chan.to_string())
-            .or_insert(bus::Bus::new(0.0))
-            .subscribe()
+    fn get(&self, chan: &str) -> Result<Bus<f64>, BusError> {
+        self.names.get(chan).cloned().ok_or_else(|| {
+            BusError::NoSuchChannel(chan.to_string())
+        })
     }
 
     pub fn publish(&mut self, chan: &str, value: f64) -> Result<(), BusError> {
-        self.busses.get_mut(chan).map(|c| c.publish(value)).ok_or(
-            BusError::NoSuchChannel(chan.to_string()),
-        )
+        let bus = self.get(chan)?;
+        self.set(&bus, value);
+        Ok(())
+    }
+
+    /// same as `publish`, for a bus given by `bus`
+    pub fn set(&mut self, bus: &Bus<f64>, value: f64) {
+        // a new value stops a running ramp
+        self.ramps.retain(|r| !r.bus.is(bus));
+        bus.publish(value);
+    }
+
+    /// go linearly from the current value to `target` in `frames` frames
+    /// (see `tick`). refused when `RAMPS` are already running.
+    pub fn ramp(&mut self, chan: &str, target: f64, frames: usize) -> Result<(), BusError> {
+        let bus = self.get(chan)?;
+        self.ramp_bus(bus, target, frames)
+    }
+
+    /// same as `ramp`, for a bus given by `bus`
+    pub fn ramp_bus(&mut self, bus: Bus<f64>, target: f64, frames: usize) -> Result<(), BusError> {
+        self.ramps.retain(|r| !r.bus.is(&bus));
+        if self.ramps.len() == RAMPS {
+            return Err(BusError::TooManyRamps);
+        }
+        self.ramps.push(BusRamp {
+            from: bus.value(),
+            bus,
+            to: target,
+            pos: 0,
+            len: frames.max(1),
+        });
+        Ok(())
+    }
+
+    /// (name, last value, subscribers) of the known buses, sorted by name
+    pub fn snapshot(&self) -> Vec<BusSnapshot> {
+        let mut buses: Vec<BusSnapshot> = self.names
+            .iter()
+            .map(|(name, bus)| {
+                BusSnapshot {
+                    name: name.clone(),
+                    value: bus.value(),
+                    subscribers: bus.sub_count(),
+                }
+            })
+            .collect();
+        buses.sort_by(|a, b| a.name.cmp(&b.name));
+        buses
+    }
+
+    /// one frame forward for the running ramps
+    pub fn tick(&mut self) {
+        if self.ramps.is_empty() {
+            return;
+        }
+        for ramp in self.ramps.iter_mut() {
+            ramp.pos += 1;
+            let value = ramp.from + (ramp.to - ramp.from) * ramp.pos as f64 / ramp.len as f64;
+            ramp.bus.publish(value);
+        }
+        self.ramps.retain(|r| r.pos < r.len);
     }
 }
 
@@ -91,4 +187,48 @@ mod tests {
         assert!(bus.publish("b", 5.0).is_ok());
         assert!(bus.publish("d", 5.0).is_err());
     }
+
+    #[test]
+    fn test_bus_ramp() {
+        let mut bus = BusSystem::new();
+        let a = bus.sub("a");
+        bus.publish("a", 1.0).unwrap();
+
+        bus.ramp("a", 2.0, 4).unwrap();
+        assert_eq!(a.value(), 1.0);
+        for expected in [1.25, 1.5, 1.75, 2.0, 2.0] {
+            bus.tick();
+            assert_eq!(a.value(), expected);
+        }
+
+        // a published value stops the ramp
+        bus.ramp("a", 0.0, 2).unwrap();
+        bus.tick();
+        assert_eq!(a.value(), 1.0);
+        bus.publish("a", 5.0).unwrap();
+        bus.tick();
+        assert_eq!(a.value(), 5.0);
+
+        assert!(bus.ramp("d", 5.0, 2).is_err());
+
+        // by handle, without the names
+        let handle = bus.bus("a");
+        bus.ramp_bus(handle.clone(), 7.0, 2).unwrap();
+        bus.tick();
+        assert_eq!(a.value(), 6.0);
+        bus.set(&handle, 1.0);
+        bus.tick();
+        assert_eq!(a.value(), 1.0);
+
+        // bounded : a ramp on a bus already ramping replaces it
+        for i in 0..RAMPS {
+            bus.sub(&format!("r{}", i));
+            bus.ramp(&format!("r{}", i), 1.0, 8).unwrap();
+        }
+        bus.ramp("r0", 2.0, 8).unwrap();
+        match bus.ramp("a", 2.0, 8) {
+            Err(BusError::TooManyRamps) => {}
+            _ => panic!("ramp not refused"),
+        }
+    }
 }
@@ -1,13 +1,18 @@
 use std::sync::{Arc, Weak};
 use std::sync::atomic;
 use std::cell::UnsafeCell;
-
-/// internal cell you can atomically write to
+use std::ptr;
+use std::hint;
+
+/// internal cell you can atomically write to.
+///
+/// the writer never waits ( RT side ). a read that overlaps a write is
+/// started again, so that a value bigger than a word ( stats .. ) can't
+/// be read half written.
 struct ReceiveCell<T> {
-    v1: UnsafeCell<T>,
-    v2: UnsafeCell<T>,
-
-    flag_: atomic::AtomicBool,
+    value: UnsafeCell<T>,
+    // odd while a write is going on
+    seq: atomic::AtomicUsize,
 }
 
 impl<T> ReceiveCell<T>
@@ -16,31 +21,32 @@ where
 {
     fn new(initial: T) -> ReceiveCell<T> {
         ReceiveCell {
-            v1: UnsafeCell::new(initial),
-            v2: UnsafeCell::new(initial),
-            flag_: atomic::AtomicBool::new(false),
+            value: UnsafeCell::new(initial),
+            seq: atomic::AtomicUsize::new(0),
         }
     }
 
     fn set(&self, val: T) {
-        if self.flag_.load(atomic::Ordering::Acquire) {
-            unsafe {
-                *self.v1.get() = val;
-            }
-            self.flag_.store(false, atomic::Ordering::Release);
-        } else {
-            unsafe {
-                *self.v2.get() = val;
-            }
-            self.flag_.store(true, atomic::Ordering::Release);
+        let seq = self.seq.load(atomic::Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), atomic::Ordering::Relaxed);
+        atomic::fence(atomic::Ordering::Release);
+        unsafe {
+            ptr::write_volatile(self.value.get(), val);
         }
+        self.seq.store(seq.wrapping_add(2), atomic::Ordering::Release);
     }
 
     fn read(&self) -> T {
-        if self.flag_.load(atomic::Ordering::Relaxed) {
-            unsafe { *self.v2.get() }
-        } else {
-            unsafe { *self.v1.get() }
+        loop {
+            let seq = self.seq.load(atomic::Ordering::Acquire);
+            if seq & 1 == 0 {
+                let val = unsafe { ptr::read_volatile(self.value.get()) };
+                atomic::fence(atomic::Ordering::Acquire);
+                if self.seq.load(atomic::Ordering::Relaxed) == seq {
+                    return val;
+                }
+            }
+            hint::spin_loop();
         }
     }
 }
@@ -92,6 +98,39 @@ where
     }
 }
 
+/// a cell written by one side, read by any number of `Reader`s : handing
+/// out a reader doesn't allocate ( see `Bus` )
+pub struct Shared<T> {
+    v: Arc<ReceiveCell<T>>,
+}
+
+impl<T> Shared<T>
+where
+    T: Copy,
+{
+    pub fn new(initial: T) -> Shared<T> {
+        Shared { v: Arc::new(ReceiveCell::new(initial)) }
+    }
+
+    /// ONE writer, like `Sender`
+    pub fn set(&self, val: T) {
+        self.v.set(val);
+    }
+
+    pub fn value(&self) -> T {
+        self.v.read()
+    }
+
+    pub fn reader(&self) -> Reader<T> {
+        Reader { v: self.v.clone() }
+    }
+
+    /// readers still there
+    pub fn readers(&self) -> usize {
+        Arc::strong_count(&self.v) - 1
+    }
+}
+
 /// create disconnectable writer/receiver pair.
 pub fn link<T: Copy>(default: T) -> (Sender<T>, Reader<T>) {
     let intern_ = Arc::new(ReceiveCell::new(default));
@@ -123,4 +162,20 @@ mod tests {
         assert_eq!(6.0, reader.value());
 
     }
+
+    #[test]
+    fn test_reader_no_tearing() {
+        let (writer, reader) = link::<(u64, u64, u64)>((0, 0, 0));
+        let hdle = spawn(move || for i in 0..100_000 {
+            writer.send((i, i, i)).unwrap();
+        });
+        loop {
+            let (a, b, c) = reader.value();
+            assert!(a == b && b == c);
+            if a == 99_999 {
+                break;
+            }
+        }
+        hdle.join().unwrap();
+    }
 }
@@ -0,0 +1,339 @@
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use sequencer::{Instrument, Pattern, Trigger};
+use synth::Synth;
+use super::mmtree::MMTree;
+use super::pbus::{Bus, BusSystem};
+use super::arena::Key;
+use super::garbage::{push, Freed};
+
+// voices ready in advance for each note of a pattern
+const VOICES_AHEAD: usize = 4;
+// room reserved for the tracks
+pub const TRACKS: usize = 64;
+
+pub enum MakerMsg {
+    Played, // a voice was taken
+    Notes(Vec<Note>),
+}
+
+// a note of the pattern, and the queue its voices go to
+pub struct Note {
+    value: f64,
+    voices: SyncSender<Box<dyn Synth>>,
+    next: Option<Box<dyn Synth>>, // built, no room in the queue yet
+}
+
+enum Step {
+    Note(usize),                     // queue in `Steps::voices`
+    Bus(String, Option<Bus<f64>>, f64), // set by `Steps::resolve`
+}
+
+/// a pattern as the RT thread plays it : the voices of its notes are built
+/// beforehand by a `VoiceMaker`, its buses are looked up on the control side
+/// ( see `resolve` ).
+pub struct Steps {
+    steps: Vec<Vec<Step>>,
+    steps_per_beat: u32,
+    voices: Vec<Receiver<Box<dyn Synth>>>,
+    wake: SyncSender<MakerMsg>,
+}
+
+impl Steps {
+    // one voice queue per note of `pattern`
+    fn new(pattern: &Pattern, wake: SyncSender<MakerMsg>) -> (Steps, Vec<Note>) {
+        let mut voices = Vec::new();
+        let mut notes = Vec::new();
+        let steps = pattern
+            .steps()
+            .iter()
+            .map(|triggers| {
+                triggers
+                    .iter()
+                    .map(|trigger| match *trigger {
+                        Trigger::Note(value) => {
+                            let (tx, rx) = sync_channel(VOICES_AHEAD);
+                            voices.push(rx);
+                            notes.push(Note {
+                                value,
+                                voices: tx,
+                                next: None,
+                            });
+                            Step::Note(voices.len() - 1)
+                        }
+                        Trigger::Bus(ref bus, value) => Step::Bus(bus.clone(), None, value),
+                    })
+                    .collect()
+            })
+            .collect();
+        let steps = Steps {
+            steps,
+            steps_per_beat: pattern.steps_per_beat(),
+            voices,
+            wake,
+        };
+        (steps, notes)
+    }
+
+    /// handles of the buses set by the steps ( the others are skipped )
+    pub fn resolve(&mut self, buses: &mut BusSystem) {
+        for step in self.steps.iter_mut().flat_map(|s| s.iter_mut()) {
+            if let Step::Bus(ref name, ref mut bus, _) = *step {
+                *bus = Some(buses.bus(name));
+            }
+        }
+    }
+}
+
+/// builds the voices of a track ahead of time, so that the instrument never
+/// runs in the RT thread.
+pub struct VoiceMaker {
+    instrument: Box<dyn Instrument>,
+    frame_t: f64,
+    notes: Vec<Note>,
+    buses: Arc<Mutex<BusSystem>>, // the voices are connected here
+}
+
+/// a `VoiceMaker` running in its own thread. the thread stops once this
+/// handle and the steps it gave are dropped.
+pub struct MakerH {
+    wake: SyncSender<MakerMsg>,
+}
+
+impl VoiceMaker {
+    pub fn new(instrument: Box<dyn Instrument>, frame_t: f64, buses: Arc<Mutex<BusSystem>>) -> VoiceMaker {
+        VoiceMaker {
+            instrument,
+            frame_t,
+            notes: Vec::new(),
+            buses,
+        }
+    }
+
+    /// steps of `pattern` taking their voices from this maker ( `wake` is
+    /// told each time one is taken ). without a thread, for the tests.
+    #[cfg(test)]
+    pub fn steps(&mut self, pattern: &Pattern, wake: SyncSender<MakerMsg>) -> Steps {
+        let (steps, notes) = Steps::new(pattern, wake);
+        self.notes = notes;
+        self.fill();
+        steps
+    }
+
+    /// voices for the queues with room
+    pub fn fill(&mut self) {
+        for note in self.notes.iter_mut() {
+            loop {
+                let voice = match note.next.take() {
+                    Some(voice) => voice,
+                    None => {
+                        let mut voice = self.instrument.voice(note.value);
+                        voice.init(self.frame_t);
+                        voice.init_parameters(self.frame_t);
+                        let mut buses = self.buses.lock().expect("can't lock the buses (a thread panicked)");
+                        voice.connect_parameters(&mut buses);
+                        voice
+                    }
+                };
+                match note.voices.try_send(voice) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(voice)) => {
+                        note.next = Some(voice);
+                        break;
+                    }
+                    // (an old pattern)
+                    Err(TrySendError::Disconnected(_)) => break,
+                }
+            }
+        }
+    }
+
+    pub fn spawn(mut self) -> MakerH {
+        // (bounded : waking the maker doesn't allocate in the RT thread)
+        let (wake, rx) = sync_channel(1);
+        thread::spawn(move || {
+            while let Ok(msg) = rx.recv() {
+                if let MakerMsg::Notes(notes) = msg {
+                    self.notes = notes;
+                }
+                self.fill();
+            }
+        });
+        MakerH { wake }
+    }
+}
+
+impl MakerH {
+    /// steps of `pattern` for the RT thread, the maker switches to its notes
+    pub fn steps(&self, pattern: &Pattern) -> Steps {
+        let (steps, notes) = Steps::new(pattern, self.wake.clone());
+        self.wake.send(MakerMsg::Notes(notes)).expect(
+            "can't send notes to the voice maker (instrument panicked)",
+        );
+        steps
+    }
+}
+
+pub struct Track {
+    id: String,
+    mixer: Key,
+    steps: Steps,
+    last_step: Option<u64>,
+}
+
+/// tempo clock and tracks. `tick` is called once per frame in the RT thread.
+///
+/// all the tracks follow the same beat : the step of a track only depends
+/// on the beat position, so a new pattern takes over in sync.
+pub struct Sequencer {
+    frame_t: f64,
+    tempo: f64, // bpm
+    swing: f64,
+    beat: f64, // position ( in beats )
+    running: bool,
+    follow: bool,
+    tracks: Vec<Track>,
+}
+
+// how far an external clock can be from ours before we jump to it (beats)
+const SYNC_TOLERANCE: f64 = 0.01;
+
+// (swung) step at `pos` ( in steps ) : odd steps are `swing` step late
+fn step_at(pos: f64, swing: f64) -> u64 {
+    let pair = (pos.max(0.) / 2.).floor();
+    let odd = if pos - pair * 2. >= 1. + swing { 1 } else { 0 };
+    pair as u64 * 2 + odd
+}
+
+impl Sequencer {
+    pub fn new(frame_t: f64) -> Sequencer {
+        Sequencer {
+            frame_t,
+            tempo: 120.,
+            swing: 0.,
+            beat: 0.,
+            running: true,
+            follow: false,
+            tracks: Vec::with_capacity(TRACKS),
+        }
+    }
+
+    pub fn set_tempo(&mut self, bpm: f64) {
+        self.tempo = bpm.max(0.);
+    }
+
+    /// 0 : straight, 1/3 : triplets feel ..
+    pub fn set_swing(&mut self, swing: f64) {
+        self.swing = swing.clamp(0., 0.9);
+    }
+
+    /// the track starts at the next step (or this one if it starts on this frame)
+    pub fn add_track(&mut self, tree: &mut MMTree, id: &str, mixer: Key, steps: Steps) -> Result<(), &str> {
+        let last_step = if self.beat == 0. {
+            None
+        } else {
+            let prev_beat = self.beat - self.tempo / 60. * self.frame_t;
+            Some(step_at(prev_beat * steps.steps_per_beat as f64, self.swing))
+        };
+        let track = Track {
+            id: id.to_string(),
+            mixer,
+            steps,
+            last_step,
+        };
+        push(&mut self.tracks, track).map_err(|track| {
+            tree.free(Freed::Track(track));
+            "too many tracks"
+        })
+    }
+
+    pub fn follow_transport(&mut self, follow: bool) {
+        self.follow = follow;
+        if !follow {
+            self.running = true;
+        }
+    }
+
+    /// (stopped : following a transport that isn't rolling)
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// external clock (JACK transport), once per period. if following it,
+    /// stops when it's not rolling, and takes its tempo and position if any.
+    pub fn sync(&mut self, rolling: bool, beat: Option<f64>, bpm: Option<f64>) {
+        if !self.follow {
+            return;
+        }
+        self.running = rolling;
+        if let Some(bpm) = bpm {
+            self.set_tempo(bpm);
+        }
+        // we keep our own clock unless the position jumped (relocation) :
+        // a step is never played twice because of a small drift.
+        if let Some(beat) = beat {
+            if (beat - self.beat).abs() > SYNC_TOLERANCE {
+                self.beat = beat;
+                for track in self.tracks.iter_mut() {
+                    track.last_step = None;
+                }
+            }
+        }
+    }
+
+    /// swap the pattern of a track, from its next step
+    pub fn set_pattern(&mut self, tree: &mut MMTree, id: &str, steps: Steps) -> Result<(), &str> {
+        match self.tracks.iter_mut().find(|t| t.id == id) {
+            Some(track) => {
+                let old = ::std::mem::replace(&mut track.steps, steps);
+                tree.free(Freed::Steps(old));
+                Ok(())
+            }
+            None => Err("no such track"),
+        }
+    }
+
+    pub fn remove_track(&mut self, tree: &mut MMTree, id: &str) -> Result<(), &str> {
+        match self.tracks.iter().position(|t| t.id == id) {
+            Some(idx) => {
+                tree.free(Freed::Track(self.tracks.remove(idx)));
+                Ok(())
+            }
+            None => Err("no such track"),
+        }
+    }
+
+    /// plays the steps starting on this frame, then moves one frame forward
+    pub fn tick(&mut self, tree: &mut MMTree) {
+        if !self.running {
+            return;
+        }
+        for track in self.tracks.iter_mut() {
+            let pos = self.beat * track.steps.steps_per_beat as f64;
+            let step = step_at(pos, self.swing);
+            if track.last_step == Some(step) {
+                continue;
+            }
+            track.last_step = Some(step);
+
+            let steps = &track.steps;
+            let idx = (step % steps.steps.len() as u64) as usize;
+            for step in steps.steps[idx].iter() {
+                // (a missing mixer or a voice not built in time is not worth
+                // stopping the music)
+                match *step {
+                    Step::Note(queue) => {
+                        if let Ok(voice) = steps.voices[queue].try_recv() {
+                            tree.add_synth(track.mixer, voice).ok();
+                            steps.wake.try_send(MakerMsg::Played).ok();
+                        }
+                    }
+                    Step::Bus(_, Some(ref bus), value) => tree.set_bus(bus, value),
+                    Step::Bus(_, None, _) => {}
+                }
+            }
+        }
+        self.beat += self.tempo / 60. * self.frame_t;
+    }
+}
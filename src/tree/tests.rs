@@ -1,23 +1,62 @@
-use super::mmtree;
-use super::mixer;
+use super::mmtree::{self, MMTree};
+use super::mixer::{self, Mixer};
+use super::sequencer::{Sequencer, Steps, VoiceMaker};
+use super::arena::{Keys, ROOT};
+use super::garbage::Freed;
+use super::pbus::BusSystem;
 
 use traits::*;
 use params::*;
 use synth::Synth;
 use efx::Efx;
 use efx::volume::{Volume, VolumeParams};
+use sequencer::Pattern;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::sync_channel;
+
+// the slots of the mixers dropped since the last call
+fn freed_slots(tree: &mut MMTree) -> Vec<usize> {
+    tree.freed()
+        .filter_map(|item| match item {
+            Freed::Slot(idx) => Some(idx),
+            _ => None,
+        })
+        .collect()
+}
+
+// parameters connected to the buses of the tree, as `MooMoot` does on the
+// control side
+fn connected<T: Parametrized>(tree: &mut MMTree, mut node: T) -> T {
+    node.connect_parameters(tree.buses());
+    node
+}
+
+fn connected_value(tree: &mut MMTree, mut value: ParamValue) -> ParamValue {
+    value.connect(tree.buses());
+    value
+}
 
 #[test]
 fn create_tree() {
 
     let mut t = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let mixer1 = keys.allocate().unwrap();
+    let mixer2 = keys.allocate().unwrap();
+    let mixer11 = keys.allocate().unwrap();
 
     // always them root mixer
     assert_eq!(t.mixer_count(), 1);
     // structure.
-    t.add_mixer("root", "mixer1").unwrap();
-    t.add_mixer("root", "mixer2").unwrap();
-    t.add_mixer("mixer1", "mixer11").unwrap();
+    t.insert_mixer(ROOT, mixer1, Mixer::new("mixer1")).unwrap();
+    t.insert_mixer(ROOT, mixer2, Mixer::new("mixer2")).unwrap();
+    t.insert_mixer(mixer1, mixer11, Mixer::new("mixer11")).unwrap();
+    // (keys are not reused)
+    assert!(t.insert_mixer(ROOT, mixer2, Mixer::new("mixer2")).is_err());
+    // a mixer that can't be added gives its slot back
+    let (missing, kid) = (keys.allocate().unwrap(), keys.allocate().unwrap());
+    assert!(t.insert_mixer(missing, kid, Mixer::new("kid")).is_err());
+    assert_eq!(freed_slots(&mut t), vec![kid.index()]);
 
     assert_eq!(t.mixer_count(), 1 + 3, " there are 4 mixers");
 
@@ -60,28 +99,73 @@ fn mixer_efx() {
     let mut mixer = mixer::Mixer::new("test");
     mixer.add_synth(Box::new(CstSynth::new(1.0)));
     let v = Box::new(Volume::new(VolumeParams::default().volume(0.6)));
-    mixer.add_efx(v);
+    assert!(mixer.add_efx(v).is_ok());
 
     assert_eq!(mixer.sample(), mono_value(0.6));
 }
 
+// plays `value` for a few frames
+struct Burst {
+    value: f64,
+    frames: usize,
+}
+
+impl Parametrized for Burst {}
+
+impl Synth for Burst {
+    fn sample(&mut self) -> SoundSample {
+        if self.frames == 0 {
+            return SoundSample::Done;
+        }
+        self.frames -= 1;
+        mono_value(self.value)
+    }
+}
+
+#[test]
+fn mixer_done_voices() {
+    use super::voices::{StealPolicy, VoiceLimit};
+
+    let mut mixer = mixer::Mixer::new("test");
+    for &(value, frames) in [(1., 1), (2., 100), (4., 2), (8., 100)].iter() {
+        mixer.add_synth(Box::new(Burst {
+            value,
+            frames,
+        }));
+    }
+    assert_eq!(mixer.sample(), mono_value(15.));
+    assert_eq!(mixer.sample(), mono_value(14.));
+    assert_eq!(mixer.sample(), mono_value(10.));
+    assert_eq!(mixer.synth_count(), 2);
+
+    // the voices left are still the oldest first
+    mixer.set_voice_limit(Some(VoiceLimit {
+        max: 1,
+        policy: StealPolicy::Oldest,
+    }));
+    assert_eq!(mixer.sample(), mono_value(8.));
+}
+
 #[test]
 fn mixer_cascade() {
 
     let mut t = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let mixer1 = keys.allocate().unwrap();
+    let mixer2 = keys.allocate().unwrap();
 
     // always them root mixer
     assert_eq!(t.mixer_count(), 1);
     // structure.
-    t.add_mixer("root", "mixer1").unwrap();
-    t.add_mixer("root", "mixer2").unwrap();
+    t.insert_mixer(ROOT, mixer1, Mixer::new("mixer1")).unwrap();
+    t.insert_mixer(ROOT, mixer2, Mixer::new("mixer2")).unwrap();
 
-    t.add_synth("mixer1", Box::new(CstSynth::new(0.1))).unwrap();
-    t.add_synth("mixer2", Box::new(CstSynth::new(0.3))).unwrap();
+    t.add_synth(mixer1, Box::new(CstSynth::new(0.1))).unwrap();
+    t.add_synth(mixer2, Box::new(CstSynth::new(0.3))).unwrap();
     assert_eq!(t.sample(), mono_value(0.4));
 
     t.add_efx(
-        "mixer2",
+        mixer2,
         Box::new(Volume::new(VolumeParams::default().volume(0.5))),
     ).unwrap(); // 0.5
     assert_eq!(t.sample(), mono_value(0.25));
@@ -120,16 +204,20 @@ fn transient_mixers() {
     // when silenced .. (think the note of an instrument)
 
     let mut tree = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let transient = keys.allocate().unwrap();
+    let not_transient = keys.allocate().unwrap();
 
-    let transient_mixer_id = tree.add_transient_mixer("root").unwrap();
+    tree.insert_mixer(ROOT, transient, Mixer::new_transient("transient"))
+        .unwrap();
 
-    tree.add_synth(&transient_mixer_id, Box::new(CstSynth::new(0.42)))
+    tree.add_synth(transient, Box::new(CstSynth::new(0.42)))
         .unwrap();
 
-    tree.add_efx(&transient_mixer_id, Box::new(ShittyEnvelope::new()))
+    tree.add_efx(transient, Box::new(ShittyEnvelope::new()))
         .unwrap();
 
-    tree.add_mixer("root", "not_transient").unwrap();
+    tree.insert_mixer(ROOT, not_transient, Mixer::new("not_transient")).unwrap();
 
     assert_eq!(tree.mixer_count(), 3);
 
@@ -138,6 +226,9 @@ fn transient_mixers() {
     }
     assert_eq!(tree.sample(), SoundSample::Silence);
     assert_eq!(tree.mixer_count(), 2);
+    // its slot can be reused
+    assert_eq!(freed_slots(&mut tree), vec![transient.index()]);
+    assert!(tree.add_synth(transient, Box::new(CstSynth::new(0.42))).is_err());
 }
 
 
@@ -149,12 +240,12 @@ struct CstSynthWithP {
 
 impl CstSynthWithP {
     fn new(params: CstSynthParams) -> CstSynthWithP {
-        CstSynthWithP { params: params }
+        CstSynthWithP { params }
     }
 }
 
 impl Parametrized for CstSynthWithP {
-    fn get_parameters(&mut self) -> &mut Parameters {
+    fn get_parameters(&mut self) -> &mut dyn Parameters {
         &mut self.params
     }
 }
@@ -169,25 +260,21 @@ impl Synth for CstSynthWithP {
 fn synth_with_params() {
     let mut tree = mmtree::MMTree::new();
     tree.add_synth(
-        "root",
+        ROOT,
         Box::new(CstSynthWithP::new(CstSynthParams::default())),
     ).unwrap();
 
     assert_eq!(tree.sample(), mono_value(1.));
 
     tree.add_synth(
-        "root",
+        ROOT,
         Box::new(CstSynthWithP::new(CstSynthParams::default().value(0.44))),
     ).unwrap();
 
     assert_eq!(tree.sample(), mono_value(1.44));
 
-    tree.add_synth(
-        "root",
-        Box::new(CstSynthWithP::new(
-            CstSynthParams::default().value("chombier"),
-        )),
-    ).unwrap();
+    let synth = connected(&mut tree, CstSynthWithP::new(CstSynthParams::default().value("chombier")));
+    tree.add_synth(ROOT, Box::new(synth)).unwrap();
 
     tree.set_bus_value("chombier", 1.0).unwrap();
     assert_eq!(tree.sample(), mono_value(2.44));
@@ -195,7 +282,672 @@ fn synth_with_params() {
     assert_eq!(tree.sample(), mono_value(1.77));
 }
 
-// will only compile on nightly ( bench unstable )
+#[test]
+fn send_and_return() {
+    let mut tree = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let dry1 = keys.allocate().unwrap();
+    let dry2 = keys.allocate().unwrap();
+    let reverb = keys.allocate().unwrap();
+
+    tree.insert_mixer(ROOT, dry1, Mixer::new("dry1")).unwrap();
+    tree.insert_mixer(ROOT, dry2, Mixer::new("dry2")).unwrap();
+    tree.insert_return(reverb, Mixer::new("reverb")).unwrap();
+
+    tree.add_synth(dry1, Box::new(CstSynth::new(1.0))).unwrap();
+    tree.add_synth(dry2, Box::new(CstSynth::new(2.0))).unwrap();
+
+    tree.add_send(dry1, reverb, ParamValue::from(0.5)).unwrap();
+    let amount = connected_value(&mut tree, ParamValue::from("send"));
+    tree.add_send(dry2, reverb, amount).unwrap();
+    tree.set_bus_value("send", 0.25).unwrap();
+
+    assert!(tree.add_send(dry1, dry2, ParamValue::from(0.5)).is_err());
+    assert!(tree.add_send(reverb, reverb, ParamValue::from(0.5)).is_err());
+    assert!(tree.add_send(keys.allocate().unwrap(), reverb, ParamValue::from(0.5)).is_err());
+
+    // dry + 0.5 * 1.0 + 0.25 * 2.0
+    assert_eq!(tree.sample(), mono_value(4.0));
+
+    // return effects are applied once to the summed sends
+    tree.add_efx(
+        reverb,
+        Box::new(Volume::new(VolumeParams::default().volume(0.5))),
+    ).unwrap();
+    assert_eq!(tree.sample(), mono_value(3.5));
+    assert_eq!(tree.mixer_count(), 4);
+}
+
+#[test]
+fn send_amount_in_silence() {
+    let mut tree = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let dry = keys.allocate().unwrap();
+    let reverb = keys.allocate().unwrap();
+    tree.insert_mixer(ROOT, dry, Mixer::new("dry")).unwrap();
+    tree.insert_return(reverb, Mixer::new("reverb")).unwrap();
+
+    // ramps over 4 frames
+    let mut amount = connected_value(&mut tree, bus("send").smoothed(4.));
+    amount.init(1.);
+    tree.add_send(dry, reverb, amount).unwrap();
+    tree.add_synth(dry, Box::new(Burst { value: 1., frames: 1 })).unwrap();
+    assert_eq!(tree.sample(), mono_value(1.0));
+    tree.set_bus_value("send", 1.).unwrap();
+    for _ in 0..8 {
+        assert_eq!(tree.sample(), SoundSample::Silence);
+    }
+
+    // the ramp is over when the sound comes
+    tree.add_synth(dry, Box::new(CstSynth::new(1.0))).unwrap();
+    assert_eq!(tree.sample(), mono_value(2.0));
+}
+
+#[test]
+fn held_clock() {
+    let mut tree = MMTree::new();
+    let bus_value = |tree: &mut MMTree| tree.snapshot().buses.iter().find(|b| b.name == "x").unwrap().value;
+    let x = tree.buses().bus("x");
+    tree.ramp_bus(x, 1., 2).unwrap();
+
+    // bus ramps wait for the clock
+    tree.hold_clock(true);
+    tree.sample();
+    tree.sample();
+    assert_eq!(bus_value(&mut tree), 0.);
+    tree.hold_clock(false);
+    tree.sample();
+    assert_eq!(bus_value(&mut tree), 0.5);
+}
+
+#[test]
+fn named_nodes() {
+    let mut tree = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let lead = keys.allocate().unwrap();
+    tree.insert_mixer(ROOT, lead, Mixer::new("lead")).unwrap();
+    tree.add_named_synth(
+        lead,
+        "osc".to_string(),
+        Box::new(CstSynthWithP::new(CstSynthParams::default())),
+    ).unwrap();
+    tree.add_synth(lead, Box::new(CstSynth::new(2.0))).unwrap();
+    tree.add_named_efx(
+        lead,
+        "vol".to_string(),
+        Box::new(Volume::new(VolumeParams::default().volume(0.5))),
+    ).unwrap();
+    assert_eq!(tree.sample(), mono_value(1.5));
+
+    let value = connected_value(&mut tree, ParamValue::from("osc_value"));
+    tree.set_node_parameter(lead, "osc".to_string(), 0, value).unwrap();
+    tree.set_bus_value("osc_value", 4.0).unwrap();
+    assert_eq!(tree.sample(), mono_value(3.0));
+    assert!(
+        tree.set_node_parameter(lead, "osc".to_string(), 1, ParamValue::from(1.))
+            .is_err()
+    );
+    // the replaced values are dropped on the control side
+    let replaced = tree.freed().filter(|item| matches!(*item, Freed::Parameter(..))).count();
+    assert_eq!(replaced, 2);
+
+    tree.remove_node(lead, "vol").unwrap();
+    assert_eq!(tree.sample(), mono_value(6.0));
+    tree.remove_node(lead, "osc").unwrap();
+    assert_eq!(tree.sample(), mono_value(2.0));
+    // already gone : fine
+    tree.remove_node(lead, "osc").unwrap();
+    tree.set_node_parameter(lead, "osc".to_string(), 0, ParamValue::from(1.))
+        .unwrap();
+}
+
+#[test]
+fn remove_mixers_and_returns() {
+    let mut tree = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let a = keys.allocate().unwrap();
+    let b = keys.allocate().unwrap();
+    let c = keys.allocate().unwrap();
+    let wet1 = keys.allocate().unwrap();
+    let wet2 = keys.allocate().unwrap();
+    tree.insert_mixer(ROOT, a, Mixer::new("a")).unwrap();
+    tree.insert_mixer(a, b, Mixer::new("b")).unwrap();
+    tree.insert_mixer(ROOT, c, Mixer::new("c")).unwrap();
+    tree.insert_return(wet1, Mixer::new("wet1")).unwrap();
+    tree.insert_return(wet2, Mixer::new("wet2")).unwrap();
+    tree.add_synth(b, Box::new(CstSynth::new(1.0))).unwrap();
+    tree.add_synth(c, Box::new(CstSynth::new(2.0))).unwrap();
+    tree.add_send(c, wet1, ParamValue::from(1.)).unwrap();
+    tree.add_send(c, wet2, ParamValue::from(0.5)).unwrap();
+    tree.add_efx(
+        wet2,
+        Box::new(Volume::new(VolumeParams::default().volume(0.5))),
+    ).unwrap();
+    // 1 + 2 + 2 + 0.5 * 0.5 * 2
+    assert_eq!(tree.sample(), mono_value(5.5));
+
+    tree.remove_mixer(a).unwrap();
+    assert_eq!(tree.mixer_count(), 4);
+    assert_eq!(tree.sample(), mono_value(4.5));
+
+    // the send to wet2 still goes to wet2
+    tree.remove_mixer(wet1).unwrap();
+    assert_eq!(tree.sample(), mono_value(2.5));
+
+    tree.remove_send(c, wet2).unwrap();
+    assert_eq!(tree.sample(), mono_value(2.0));
+
+    assert!(tree.remove_mixer(ROOT).is_err());
+    assert!(tree.remove_mixer(a).is_err());
+}
+
+#[test]
+fn full_mixers() {
+    let mut tree = MMTree::new();
+    let mut keys = Keys::new();
+
+    // a full mixer refuses new nodes rather than growing
+    for _ in 0..64 {
+        tree.add_synth(ROOT, Box::new(CstSynth::new(0.1))).unwrap();
+    }
+    assert!(tree.add_synth(ROOT, Box::new(CstSynth::new(0.1))).is_err());
+    for _ in 0..16 {
+        tree.insert_mixer(ROOT, keys.allocate().unwrap(), Mixer::new("sub")).unwrap();
+    }
+    let refused = keys.allocate().unwrap();
+    assert!(tree.insert_mixer(ROOT, refused, Mixer::new("sub")).is_err());
+
+    // what is refused or removed goes back to the control side
+    let lead = keys.allocate().unwrap();
+    let fx = || Box::new(Volume::new(VolumeParams::default()));
+    assert!(tree.add_named_efx(lead, "fx".to_string(), fx()).is_err());
+    let freed: Vec<_> = tree.freed().collect();
+    match freed[..] {
+        [Freed::Synth(None, _), Freed::Mixer(_), Freed::Slot(idx), Freed::Efx(Some(ref id), _)] => {
+            assert_eq!(idx, refused.index());
+            assert_eq!(id, "fx");
+        }
+        _ => panic!("unexpected freed items"),
+    }
+
+    tree.insert_return(lead, Mixer::new("lead")).unwrap();
+    tree.add_named_efx(lead, "fx".to_string(), fx()).unwrap();
+    tree.remove_node(lead, "fx").unwrap();
+    match tree.freed().collect::<Vec<_>>()[..] {
+        [Freed::Efx(Some(ref id), _)] => assert_eq!(id, "fx"),
+        _ => panic!("unexpected freed items"),
+    }
+}
+
+#[test]
+fn capacities_and_garbage() {
+    let capacities = mmtree::Capacities {
+        voices: 2,
+        sub_mixers: 1,
+        ..mmtree::Capacities::default()
+    };
+    let mut tree = MMTree::with_capacities(&capacities);
+    let mut keys = Keys::new();
+    let mixer = |name| Mixer::with_capacities(name, &capacities);
+    for _ in 0..2 {
+        tree.add_synth(ROOT, Box::new(CstSynth::new(0.1))).unwrap();
+    }
+    assert!(tree.add_synth(ROOT, Box::new(CstSynth::new(0.1))).is_err());
+    tree.insert_mixer(ROOT, keys.allocate().unwrap(), mixer("a")).unwrap();
+    assert!(tree.insert_mixer(ROOT, keys.allocate().unwrap(), mixer("b")).is_err());
+
+    // the channel takes what it can, the rest waits for the next period
+    let (tx, rx) = sync_channel(2);
+    tree.send_freed(&tx);
+    assert_eq!(rx.try_iter().count(), 2);
+    tree.send_freed(&tx);
+    assert_eq!(rx.try_iter().count(), 1);
+
+    // past the room for one period, the commands wait
+    let gone = keys.allocate().unwrap();
+    while tree.has_room() {
+        assert!(tree.add_synth(gone, Box::new(CstSynth::new(0.1))).is_err());
+    }
+    assert_eq!(tree.freed().count(), 1024 - 4 + 1);
+    assert!(tree.has_room());
+}
+
+#[test]
+fn tree_snapshot() {
+    let mut tree = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let lead = keys.allocate().unwrap();
+    let wet = keys.allocate().unwrap();
+    let lead_transient = keys.allocate().unwrap();
+    tree.insert_mixer(ROOT, lead, Mixer::new("lead")).unwrap();
+    tree.insert_mixer(lead, lead_transient, Mixer::new_transient("transient"))
+        .unwrap();
+    tree.insert_return(wet, Mixer::new("wet")).unwrap();
+    let synth = connected(&mut tree, CstSynthWithP::new(CstSynthParams::default().value("v")));
+    tree.add_named_synth(lead, "osc".to_string(), Box::new(synth)).unwrap();
+    tree.add_synth(lead, Box::new(CstSynth::new(1.))).unwrap();
+    tree.add_efx(
+        lead,
+        Box::new(Volume::new(VolumeParams::default().volume(0.5))),
+    ).unwrap();
+    let amount = connected_value(&mut tree, ParamValue::from("v * 0.1"));
+    tree.add_send(lead, wet, amount).unwrap();
+    tree.set_bus_value("v", 2.).unwrap();
+
+    let snap = tree.snapshot();
+    assert_eq!(snap.mixer_count(), 4);
+    assert_eq!(snap.returns[0].id, "wet");
+
+    let lead = snap.find_mixer("lead").unwrap();
+    assert!(!lead.transient);
+    assert!(lead.mixers[0].transient);
+    assert_eq!(lead.synths.len(), 2);
+    assert_eq!(lead.synths[0].id, Some("osc".to_string()));
+    assert_eq!(lead.synths[0].param("value"), Some(2.));
+    assert_eq!(lead.synths[1].type_name, "unknown");
+    assert_eq!(lead.efx[0].type_name, "volume");
+    assert_eq!(lead.efx[0].param("volume"), Some(0.5));
+    assert_eq!(lead.sends[0].to, "wet");
+    assert_eq!(lead.sends[0].amount, 0.2);
+
+    // the synth parameter and the send formula
+    let v = snap.bus("v").unwrap();
+    assert_eq!((v.value, v.subscribers), (2., 2));
+}
+
+#[test]
+fn patch_round_trip() {
+    use patch::Patch;
+    use synth::sine::{Sine, SineParams};
+
+    let mut tree = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let (lead, bass, voice, wet) = (keys.allocate().unwrap(), keys.allocate().unwrap(), keys.allocate().unwrap(), keys.allocate().unwrap());
+    tree.insert_mixer(ROOT, lead, Mixer::new("lead")).unwrap();
+    tree.insert_mixer(lead, bass, Mixer::new("bass")).unwrap();
+    tree.insert_mixer(lead, voice, Mixer::new_transient("voice")).unwrap();
+    tree.insert_return(wet, Mixer::new("wet")).unwrap();
+    let sine = connected(&mut tree, Sine::new(SineParams::default().amplitude(0.5).frequency("lfo * 100 + 220")));
+    tree.add_synth(lead, Box::new(sine)).unwrap();
+    let sine = connected(&mut tree, Sine::new(SineParams::default().frequency(bus("bass.freq").smoothed(0.1))));
+    tree.add_synth(bass, Box::new(sine)).unwrap();
+    let volume = connected(&mut tree, Volume::new(VolumeParams::default().volume("vol")));
+    tree.add_efx(lead, Box::new(volume)).unwrap();
+    tree.add_efx(wet, Box::new(Volume::new(VolumeParams::default()))).unwrap();
+    let amount = connected_value(&mut tree, ParamValue::from("v * 0.1"));
+    tree.add_send(lead, wet, amount).unwrap();
+    tree.add_send(bass, wet, ParamValue::from(0.25)).unwrap();
+
+    let patch = Patch::from(&tree.snapshot());
+    assert_eq!(patch.to_string().parse::<Patch>().unwrap(), patch);
+    let text = "return wet\n\
+                efx wet volume\n\
+                mixer lead\n\
+                synth lead sine amplitude=0.5 frequency=\"lfo * 100 + 220\"\n\
+                efx lead volume volume=vol\n\
+                mixer bass lead\n\
+                synth bass sine frequency=bass.freq\n\
+                send lead wet \"v * 0.1\"\n\
+                send bass wet 0.25";
+    assert_eq!(patch, text.parse().unwrap());
+}
+
+#[test]
+fn mixer_meters() {
+    use super::meter::meter;
+
+    let mut tree = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let lead = keys.allocate().unwrap();
+    let wet = keys.allocate().unwrap();
+    let nope = keys.allocate().unwrap();
+    tree.insert_mixer(ROOT, lead, Mixer::new("lead")).unwrap();
+    tree.insert_return(wet, Mixer::new("wet")).unwrap();
+    tree.add_synth(lead, Box::new(CstSynth::new(0.5))).unwrap();
+    tree.add_efx(
+        lead,
+        Box::new(Volume::new(VolumeParams::default().volume(0.5))),
+    ).unwrap();
+    tree.add_send(lead, wet, ParamValue::from(1.)).unwrap();
+
+    let (lead_meter, probe) = meter(2);
+    tree.add_meter(lead, probe).unwrap();
+    let (wet_meter, probe) = meter(2);
+    tree.add_meter(wet, probe).unwrap();
+    let (output, probe) = meter(2);
+    tree.add_output_meter(probe).unwrap();
+    assert!(tree.add_meter(nope, meter(2).1).is_err());
+
+    tree.sample();
+    tree.sample();
+    // after the effects
+    assert_eq!((lead_meter.peak(), lead_meter.rms()), (0.25, 0.25));
+    assert_eq!(wet_meter.peak(), 0.25);
+    // the returns are in the main output
+    assert_eq!(output.peak(), 0.5);
+}
+
+#[test]
+fn voice_limit() {
+    use super::voices::{StealPolicy, VoiceLimit};
+
+    let mut tree = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let pad = keys.allocate().unwrap();
+    let nope = keys.allocate().unwrap();
+    tree.insert_mixer(ROOT, pad, Mixer::new("pad")).unwrap();
+    for _ in 0..3 {
+        tree.add_synth(pad, Box::new(CstSynth::new(1.))).unwrap();
+    }
+    let limit = VoiceLimit {
+        max: 2,
+        policy: StealPolicy::Oldest,
+    };
+    tree.set_release(2);
+    // over the limit : the oldest fades out
+    tree.set_voice_limit(pad, Some(limit)).unwrap();
+    assert_eq!(tree.sample(), mono_value(3.));
+    assert_eq!(tree.sample(), mono_value(2.5));
+    assert_eq!(tree.sample(), mono_value(2.));
+    assert_eq!(tree.synth_count(), 2);
+
+    tree.add_synth(pad, Box::new(CstSynth::new(1.))).unwrap();
+    tree.sample();
+    tree.sample();
+    tree.sample();
+    assert_eq!(tree.synth_count(), 2);
+    assert!(tree.set_voice_limit(nope, None).is_err());
+
+    tree.set_voice_limit(pad, None).unwrap();
+    tree.add_synth(pad, Box::new(CstSynth::new(1.))).unwrap();
+    assert_eq!(tree.sample(), mono_value(3.));
+}
+
+#[test]
+fn release_removed_nodes() {
+    let mut tree = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let pad = keys.allocate().unwrap();
+    let drums = keys.allocate().unwrap();
+    let wet = keys.allocate().unwrap();
+    tree.set_release(2);
+    tree.insert_mixer(ROOT, pad, Mixer::new("pad")).unwrap();
+    tree.insert_mixer(ROOT, drums, Mixer::new("drums")).unwrap();
+    tree.insert_return(wet, Mixer::new("wet")).unwrap();
+    tree.add_named_synth(pad, "a".to_string(), Box::new(CstSynth::new(1.))).unwrap();
+    tree.add_synth(pad, Box::new(CstSynth::new(1.))).unwrap();
+    tree.add_synth(drums, Box::new(CstSynth::new(2.))).unwrap();
+    tree.add_send(drums, wet, ParamValue::from(1.)).unwrap();
+    assert_eq!(tree.sample(), mono_value(6.));
+
+    // the node fades out ..
+    tree.remove_node(pad, "a").unwrap();
+    // .. and can be replaced right away
+    tree.add_named_synth(pad, "a".to_string(), Box::new(CstSynth::new(1.))).unwrap();
+    assert_eq!(tree.snapshot().find_mixer("pad").unwrap().synths.len(), 2);
+    // the mixer and its sends too
+    tree.remove_mixer(drums).unwrap();
+    assert!(tree.snapshot().find_mixer("drums").is_none());
+    // removed twice : fine
+    tree.remove_mixer(drums).unwrap();
+    assert!(tree.add_synth(drums, Box::new(CstSynth::new(2.))).is_err());
+
+    assert_eq!(tree.sample(), mono_value(7.));
+    assert_eq!(tree.sample(), mono_value(4.5));
+    assert_eq!(tree.sample(), mono_value(2.));
+    assert_eq!((tree.synth_count(), tree.mixer_count()), (2, 3));
+}
+
+#[test]
+fn move_mixers() {
+    let mut tree = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let a = keys.allocate().unwrap();
+    let a1 = keys.allocate().unwrap();
+    let a2 = keys.allocate().unwrap();
+    let b = keys.allocate().unwrap();
+    let wet = keys.allocate().unwrap();
+    let nope = keys.allocate().unwrap();
+    tree.insert_mixer(ROOT, a, Mixer::new("a")).unwrap();
+    tree.insert_mixer(a, a1, Mixer::new("a1")).unwrap();
+    tree.insert_mixer(a1, a2, Mixer::new("a2")).unwrap();
+    tree.insert_mixer(ROOT, b, Mixer::new("b")).unwrap();
+    tree.insert_return(wet, Mixer::new("wet")).unwrap();
+    tree.add_synth(a1, Box::new(CstSynth::new(1.))).unwrap();
+    tree.add_efx(
+        a1,
+        Box::new(Volume::new(VolumeParams::default().volume(0.5))),
+    ).unwrap();
+    tree.add_synth(a2, Box::new(CstSynth::new(1.))).unwrap();
+    tree.add_efx(
+        b,
+        Box::new(Volume::new(VolumeParams::default().volume(0.5))),
+    ).unwrap();
+    assert_eq!(tree.sample(), mono_value(1.));
+
+    // with its synths, effects and sub mixers
+    tree.move_mixer(a1, b).unwrap();
+    assert_eq!(tree.sample(), mono_value(0.5));
+    let snapshot = tree.snapshot();
+    assert!(snapshot.find_mixer("a").unwrap().mixers.is_empty());
+    let moved = &snapshot.find_mixer("b").unwrap().mixers[0];
+    assert_eq!(moved.id, "a1");
+    assert_eq!(moved.mixers[0].id, "a2");
+    assert_eq!(moved.efx.len(), 1);
+    assert_eq!(tree.mixer_count(), 6);
+
+    // under a return
+    tree.move_mixer(a2, wet).unwrap();
+    assert_eq!(tree.sample(), mono_value(1.25));
+
+    assert!(tree.move_mixer(a1, a1).is_err());
+    assert!(tree.move_mixer(b, a1).is_err());
+    assert!(tree.move_mixer(ROOT, a).is_err());
+    assert!(tree.move_mixer(wet, a).is_err());
+    assert!(tree.move_mixer(nope, a).is_err());
+    assert!(tree.move_mixer(a, nope).is_err());
+    assert_eq!(tree.mixer_count(), 6);
+}
+
+#[test]
+fn profile_mixers() {
+    let mut tree = mmtree::MMTree::new();
+    let mut keys = Keys::new();
+    let lead = keys.allocate().unwrap();
+    let lead_sub = keys.allocate().unwrap();
+    let bass = keys.allocate().unwrap();
+    let wet = keys.allocate().unwrap();
+    tree.insert_mixer(ROOT, lead, Mixer::new("lead")).unwrap();
+    tree.insert_mixer(lead, lead_sub, Mixer::new("lead_sub")).unwrap();
+    tree.insert_return(wet, Mixer::new("wet")).unwrap();
+    tree.add_synth(ROOT, Box::new(CstSynth::new(0.5))).unwrap();
+    tree.add_synth(lead_sub, Box::new(CstSynth::new(0.5))).unwrap();
+    tree.add_synth(wet, Box::new(CstSynth::new(0.5))).unwrap();
+    assert_eq!((tree.synth_count(), tree.mixer_count()), (3, 4));
+
+    // nothing measured by default
+    tree.sample();
+    assert_eq!(tree.take_mixer_times(), (0, vec![]));
+
+    tree.set_profiling(true);
+    // top level mixers added later are measured too
+    tree.insert_mixer(ROOT, bass, Mixer::new("bass")).unwrap();
+    for _ in 0..10 {
+        tree.sample();
+    }
+    let (frames, times) = tree.take_mixer_times();
+    assert_eq!(frames, 10);
+    let mut ids: Vec<&str> = times.iter().map(|t| t.0.as_str()).collect();
+    ids.sort();
+    assert_eq!(ids, vec!["bass", "lead", "wet"]);
+    assert_eq!(tree.take_mixer_times().0, 0);
+
+    tree.set_profiling(false);
+    tree.sample();
+    assert_eq!(tree.take_mixer_times(), (0, vec![]));
+}
+
+// ticks the sequencer, returns (frame, note) for each note played
+fn play(
+    seq: &mut Sequencer,
+    tree: &mut mmtree::MMTree,
+    maker: &mut VoiceMaker,
+    played: &Arc<Mutex<Vec<f64>>>,
+    frames: usize,
+) -> Vec<(usize, f64)> {
+    let mut notes = Vec::new();
+    for frame in 0..frames {
+        maker.fill();
+        let before = played.lock().unwrap().len();
+        seq.tick(tree);
+        tree.sample();
+        for note in played.lock().unwrap()[before..].iter() {
+            notes.push((frame, *note));
+        }
+    }
+    notes
+}
+
+// a voice telling when it starts playing
+struct NoteSynth {
+    note: f64,
+    played: Option<Arc<Mutex<Vec<f64>>>>,
+}
+
+impl Synth for NoteSynth {
+    fn sample(&mut self) -> SoundSample {
+        if let Some(played) = self.played.take() {
+            played.lock().unwrap().push(self.note);
+        }
+        mono_value(0.)
+    }
+}
+
+impl Parametrized for NoteSynth {}
+
+// a maker of voices recording what they play
+fn recorder(played: &Arc<Mutex<Vec<f64>>>) -> VoiceMaker {
+    let played = played.clone();
+    VoiceMaker::new(
+        Box::new(move |note: f64| -> Box<dyn Synth> {
+            Box::new(NoteSynth {
+                note,
+                played: Some(played.clone()),
+            })
+        }),
+        1. / 128.,
+        Arc::new(Mutex::new(BusSystem::new())),
+    )
+}
+
+// (the maker isn't woken up : `play` fills it)
+fn steps(maker: &mut VoiceMaker, pattern: Pattern) -> Steps {
+    let (wake, _) = sync_channel(1);
+    maker.steps(&pattern, wake)
+}
+
+#[test]
+fn sequencer_steps() {
+    let mut tree = mmtree::MMTree::new();
+    // 60 bpm, 128 frames per beat : 2 steps per beat => 64 frames per step.
+    let mut seq = Sequencer::new(1. / 128.);
+    seq.set_tempo(60.);
+
+    let played = Arc::new(Mutex::new(Vec::new()));
+    let mut maker = recorder(&played);
+    let pattern = Pattern::from_notes(2, &[Some(1.), None, Some(3.), Some(4.)]);
+    seq.add_track(&mut tree, "t", ROOT, steps(&mut maker, pattern)).unwrap();
+
+    assert_eq!(
+        play(&mut seq, &mut tree, &mut maker, &played, 300),
+        vec![(0, 1.), (128, 3.), (192, 4.), (256, 1.)]
+    );
+
+    // swapped in sync : step 5 (frame 320) is the second step of the new pattern
+    let pattern = Pattern::from_notes(2, &[Some(10.), Some(11.)]);
+    seq.set_pattern(&mut tree, "t", steps(&mut maker, pattern))
+        .unwrap();
+    assert_eq!(play(&mut seq, &mut tree, &mut maker, &played, 100), vec![(20, 11.), (84, 10.)]);
+    let pattern = Pattern::new(1, 1);
+    assert!(seq.set_pattern(&mut tree, "nope", steps(&mut maker, pattern)).is_err());
+
+    seq.remove_track(&mut tree, "t").unwrap();
+    assert_eq!(play(&mut seq, &mut tree, &mut maker, &played, 300), vec![]);
+}
+
+#[test]
+fn sequencer_swing() {
+    let mut tree = mmtree::MMTree::new();
+    let mut seq = Sequencer::new(1. / 128.);
+    seq.set_tempo(60.);
+    seq.set_swing(0.5);
+
+    let played = Arc::new(Mutex::new(Vec::new()));
+    let mut maker = recorder(&played);
+    let pattern = Pattern::from_notes(2, &[Some(1.), Some(2.)]);
+    seq.add_track(&mut tree, "t", ROOT, steps(&mut maker, pattern)).unwrap();
+
+    // odd steps are half a step late
+    assert_eq!(
+        play(&mut seq, &mut tree, &mut maker, &played, 256),
+        vec![(0, 1.), (96, 2.), (128, 1.), (224, 2.)]
+    );
+}
+
+#[test]
+fn sequencer_follows_transport() {
+    let mut tree = mmtree::MMTree::new();
+    let mut seq = Sequencer::new(1. / 128.);
+    let played = Arc::new(Mutex::new(Vec::new()));
+    let mut maker = recorder(&played);
+    let pattern = Pattern::from_notes(2, &[Some(1.), Some(2.), Some(3.), Some(4.)]);
+    seq.add_track(&mut tree, "t", ROOT, steps(&mut maker, pattern)).unwrap();
+
+    // not following : ignored
+    seq.sync(false, None, None);
+    assert_eq!(play(&mut seq, &mut tree, &mut maker, &played, 1), vec![(0, 1.)]);
+
+    seq.follow_transport(true);
+    seq.sync(false, Some(0.), Some(60.));
+    assert_eq!(play(&mut seq, &mut tree, &mut maker, &played, 200), vec![]);
+
+    // rolling from beat 1 (relocated) at 60 bpm
+    seq.sync(true, Some(1.), Some(60.));
+    assert_eq!(
+        play(&mut seq, &mut tree, &mut maker, &played, 100),
+        vec![(0, 3.), (64, 4.)]
+    );
+    // a small drift doesn't replay the step
+    seq.sync(true, Some(1.78), Some(60.));
+    assert_eq!(play(&mut seq, &mut tree, &mut maker, &played, 28), vec![]);
+}
+
+#[test]
+fn sequencer_bus_and_voices() {
+    let mut tree = mmtree::MMTree::new();
+    let synth = connected(&mut tree, CstSynthWithP::new(CstSynthParams::default().value("lvl")));
+    tree.add_synth(ROOT, Box::new(synth)).unwrap();
+
+    let mut seq = Sequencer::new(1. / 128.);
+    seq.set_tempo(60.);
+    let played = Arc::new(Mutex::new(Vec::new()));
+    let mut maker = recorder(&played);
+    let pattern = Pattern::new(2, 1).bus(0, "lvl", 0.5).bus(1, "lvl", 0.25).note(1, 1.);
+    let mut steps = steps(&mut maker, pattern);
+    steps.resolve(tree.buses());
+    seq.add_track(&mut tree, "t", ROOT, steps).unwrap();
+
+    seq.tick(&mut tree);
+    assert_eq!(tree.sample(), mono_value(0.5));
+    for _ in 0..128 {
+        seq.tick(&mut tree);
+    }
+    assert_eq!(tree.sample(), mono_value(0.25));
+    assert_eq!(*played.lock().unwrap(), vec![1.]);
+}
+
+// will only compile on nightly ( bench unstable ) : --features nightly
+#[cfg(feature = "nightly")]
 mod benches {
     extern crate test;
     use super::*;
@@ -213,27 +965,29 @@ mod benches {
 
         let mut tree = mmtree::MMTree::new();
 
-        tree.add_synth("root", Box::new(Sine::new(SineParams::default())))
+        tree.add_synth(ROOT, Box::new(Sine::new(SineParams::default())))
             .unwrap();
 
+        let mut keys = Keys::new();
         let mut stack = Vec::new();
-        stack.push((String::from("root"), 0));
+        stack.push((ROOT, 0));
 
         while !stack.is_empty() {
 
-            let (parent_id, level) = stack.pop().unwrap();
+            let (parent, level) = stack.pop().unwrap();
 
             if level < TREE_DEPTH {
                 for j in 0..TREE_WIDTH {
-                    let new_id = format!("{}_{}", parent_id, j);
-                    println!("{} -> {}", parent_id, new_id);
-                    tree.add_mixer(&parent_id, &new_id).unwrap();
-                    stack.push((new_id, level + 1));
+                    let new = keys.allocate().unwrap();
+                    println!("{:?} -> {:?}", parent, new);
+                    let name = format!("{}_{}", level, j);
+                    tree.insert_mixer(parent, new, Mixer::new(&name)).unwrap();
+                    stack.push((new, level + 1));
                 }
             }
             for _ in 0..2 {
-                let synth = Box::new(Sine::new(SineParams::default().frequency("f")));
-                tree.add_synth(&parent_id, synth).unwrap();
+                let synth = connected(&mut tree, Sine::new(SineParams::default().frequency("f")));
+                tree.add_synth(parent, Box::new(synth)).unwrap();
             }
 
         }
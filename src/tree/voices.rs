@@ -0,0 +1,286 @@
+use synth::Synth;
+use traits::{SampleValue, SoundSample};
+
+/// which voice goes when a mixer is full ( see `MooMoot::set_voice_limit` )
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StealPolicy {
+    /// the one playing for the longest time
+    Oldest,
+    /// the lowest level right now
+    Quietest,
+    /// the lowest priority ( see `MooMoot::add_synth_with_priority` ), then
+    /// the oldest
+    LowestPriority,
+}
+
+/// at most `max` synths playing in a mixer ( the ones of its sub mixers
+/// are not counted )
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoiceLimit {
+    pub max: usize,
+    pub policy: StealPolicy,
+}
+
+// level follower release, per frame
+const LEVEL_DECAY: f64 = 0.999;
+
+/// a linear ramp to zero
+pub struct Fade {
+    left: usize,
+    length: usize,
+}
+
+impl Fade {
+    pub fn new(frames: usize) -> Fade {
+        Fade {
+            left: frames,
+            length: frames.max(1),
+        }
+    }
+
+    /// the gain for the next frame, `None` once faded out
+    pub fn next(&mut self) -> Option<f64> {
+        if self.left == 0 {
+            None
+        } else {
+            let gain = self.left as f64 / self.length as f64;
+            self.left -= 1;
+            Some(gain)
+        }
+    }
+}
+
+/// a synth in a mixer
+pub struct Voice {
+    /// for nodes created from a patch
+    pub id: Option<String>,
+    pub synth: Box<dyn Synth>,
+    pub priority: i32,
+    /// fade out length, in frames
+    pub release: usize,
+    level: f64,
+    last: Option<SampleValue>,
+    fade: Option<Fade>,
+    // the synth is done : its last value is faded out
+    tail: Option<SampleValue>,
+}
+
+impl Voice {
+    pub fn new(id: Option<String>, synth: Box<dyn Synth>, priority: i32) -> Voice {
+        Voice {
+            id,
+            synth,
+            priority,
+            release: 0,
+            level: 0.,
+            last: None,
+            fade: None,
+            tail: None,
+        }
+    }
+
+    pub fn sample(&mut self) -> SoundSample {
+        if let Some(ref mut fade) = self.fade {
+            let gain = match fade.next() {
+                Some(gain) => gain,
+                None => return SoundSample::Done,
+            };
+            if let Some(ref last) = self.tail {
+                return SoundSample::Sample(last.clone() * gain);
+            }
+            return match self.synth.sample() {
+                SoundSample::Sample(value) => SoundSample::Sample(value * gain),
+                other => other,
+            };
+        }
+        match self.synth.sample() {
+            SoundSample::Sample(value) => {
+                let peak = match value {
+                    SampleValue::Mono(x) => x.abs(),
+                    SampleValue::Stereo(r, l) => r.abs().max(l.abs()),
+                };
+                self.level = peak.max(self.level * LEVEL_DECAY);
+                self.last = Some(value.clone());
+                SoundSample::Sample(value)
+            }
+            SoundSample::Done => {
+                // (no jump to zero when stopping mid-waveform)
+                if self.release > 0 && self.last.is_some() {
+                    self.tail = self.last.take();
+                    self.fade = Some(Fade::new(self.release));
+                    self.sample()
+                } else {
+                    SoundSample::Done
+                }
+            }
+            other => {
+                self.last = None;
+                other
+            }
+        }
+    }
+
+    /// fades out in `release` frames, then is done
+    pub fn release(&mut self) {
+        if self.fade.is_none() {
+            self.fade = Some(Fade::new(self.release));
+        }
+    }
+
+    /// released : it'll be gone soon
+    pub fn is_fading(&self) -> bool {
+        self.fade.is_some()
+    }
+}
+
+// the voice to steal, by position ( the oldest first )
+fn victim(voices: &[Voice], policy: StealPolicy) -> Option<usize> {
+    let playing = voices.iter().enumerate().filter(|&(_, v)| !v.is_fading());
+    // (the first one wins ties : the oldest)
+    let lowest = |key: &dyn Fn(&Voice) -> f64| {
+        playing
+            .clone()
+            .fold(None, |best: Option<(usize, f64)>, (i, v)| {
+                let k = key(v);
+                match best {
+                    Some((_, b)) if b <= k => best,
+                    _ => Some((i, k)),
+                }
+            })
+            .map(|(i, _)| i)
+    };
+    match policy {
+        StealPolicy::Oldest => playing.clone().next().map(|(i, _)| i),
+        StealPolicy::Quietest => lowest(&|v| v.level),
+        StealPolicy::LowestPriority => lowest(&|v| v.priority as f64),
+    }
+}
+
+/// steals voices until there's room for `room` more ones
+pub fn make_room(voices: &mut [Voice], limit: &VoiceLimit, room: usize) {
+    let max = limit.max.saturating_sub(room);
+    let mut playing = voices.iter().filter(|v| !v.is_fading()).count();
+    while playing > max {
+        match victim(voices, limit.policy) {
+            Some(i) => {
+                voices[i].release();
+            }
+            None => return,
+        }
+        playing -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use params::Parametrized;
+    use traits::mono_value;
+
+    struct Cst(f64);
+
+    impl Parametrized for Cst {}
+
+    impl Synth for Cst {
+        fn sample(&mut self) -> SoundSample {
+            mono_value(self.0)
+        }
+    }
+
+    fn voices(levels: &[(f64, i32)]) -> Vec<Voice> {
+        let mut voices: Vec<Voice> = levels
+            .iter()
+            .map(|&(level, priority)| Voice::new(None, Box::new(Cst(level)), priority))
+            .collect();
+        for voice in voices.iter_mut() {
+            voice.sample();
+        }
+        voices
+    }
+
+    fn stolen(voices: &[Voice]) -> Vec<bool> {
+        voices.iter().map(|v| v.is_fading()).collect()
+    }
+
+    #[test]
+    fn test_policies() {
+        let levels = [(0.5, 1), (0.2, 0), (0.1, 2), (0.2, 0)];
+        let limit = |max, policy| {
+            VoiceLimit {
+                max,
+                policy,
+            }
+        };
+
+        let mut v = voices(&levels);
+        make_room(&mut v, &limit(3, StealPolicy::Oldest), 1);
+        assert_eq!(stolen(&v), vec![true, true, false, false]);
+
+        let mut v = voices(&levels);
+        make_room(&mut v, &limit(4, StealPolicy::Quietest), 1);
+        assert_eq!(stolen(&v), vec![false, false, true, false]);
+
+        let mut v = voices(&levels);
+        make_room(&mut v, &limit(3, StealPolicy::LowestPriority), 1);
+        assert_eq!(stolen(&v), vec![false, true, false, true]);
+
+        // room enough
+        let mut v = voices(&levels);
+        make_room(&mut v, &limit(5, StealPolicy::Oldest), 1);
+        assert_eq!(stolen(&v), vec![false; 4]);
+    }
+
+    #[test]
+    fn test_fade_out() {
+        let mut voice = Voice::new(None, Box::new(Cst(1.)), 0);
+        voice.release = 4;
+        voice.release();
+        let out: Vec<SoundSample> = (0..5).map(|_| voice.sample()).collect();
+        assert_eq!(
+            out,
+            vec![
+                mono_value(1.),
+                mono_value(0.75),
+                mono_value(0.5),
+                mono_value(0.25),
+                SoundSample::Done,
+            ]
+        );
+    }
+
+    struct Burst(usize);
+
+    impl Parametrized for Burst {}
+
+    impl Synth for Burst {
+        fn sample(&mut self) -> SoundSample {
+            if self.0 == 0 {
+                SoundSample::Done
+            } else {
+                self.0 -= 1;
+                mono_value(0.8)
+            }
+        }
+    }
+
+    #[test]
+    fn test_done_tail() {
+        let mut voice = Voice::new(None, Box::new(Burst(1)), 0);
+        voice.release = 2;
+        let out: Vec<SoundSample> = (0..4).map(|_| voice.sample()).collect();
+        assert_eq!(
+            out,
+            vec![
+                mono_value(0.8),
+                mono_value(0.8),
+                mono_value(0.4),
+                SoundSample::Done,
+            ]
+        );
+
+        // no release : done right away
+        let mut voice = Voice::new(None, Box::new(Burst(1)), 0);
+        voice.sample();
+        assert_eq!(voice.sample(), SoundSample::Done);
+    }
+}
@@ -0,0 +1,31 @@
+use std::f64::consts::PI;
+
+/// a free running sine low frequency oscillator, for modulation effects.
+pub struct Lfo {
+    phase: f64, // in [0, 1[
+    frame_t: f64,
+}
+
+impl Lfo {
+    /// `phase` is the starting phase, as a fraction of a period
+    pub fn new(phase: f64) -> Lfo {
+        Lfo {
+            phase: phase.fract(),
+            frame_t: 0.,
+        }
+    }
+
+    pub fn init(&mut self, frame_t: f64) {
+        self.frame_t = frame_t;
+    }
+
+    /// current value (in [-1, 1]) then advance one frame at `rate` Hz
+    pub fn next(&mut self, rate: f64) -> f64 {
+        let v = (2. * PI * self.phase).sin();
+        self.phase = (self.phase + rate * self.frame_t).fract();
+        if self.phase < 0. {
+            self.phase += 1.;
+        }
+        v
+    }
+}
@@ -1 +1,3 @@
 pub mod ringbuffer;
+pub mod lfo;
+pub mod scheduler;
@@ -21,11 +21,19 @@ impl FixedRingBuffer {
         mem::swap(unsafe { self.queue.get_unchecked_mut(self.idx) }, elem);
     }
 
-    /// reset all elements
-    pub fn set_all(&mut self, elem: f64) {
-        for e in self.queue.iter_mut() {
-            *e = elem;
-        }
+    /// read the element `delay` samples behind the last input, linearly
+    /// interpolated between the two closest elements.
+    ///
+    /// `delay` is clamped to `[0, len - 1]` (0 is the last queued element)
+    pub fn read_frac(&self, delay: f64) -> f64 {
+        let len = self.len();
+        let delay = delay.max(0.).min((len - 1) as f64);
+        let whole = delay as usize;
+        let frac = delay - whole as f64;
+
+        let a = self.queue[(self.idx + whole) % len];
+        let b = self.queue[(self.idx + whole + 1) % len];
+        a + (b - a) * frac
     }
 }
 
@@ -58,4 +66,22 @@ mod tests {
 
         }
     }
+
+    #[test]
+    fn test_fractional_read() {
+
+        let mut ring = FixedRingBuffer::from(vec![0.0; 10]);
+
+        for i in 1..6 {
+            let mut value = i as f64;
+            ring.queue(&mut value);
+        }
+        // last input is 5, the one before is 4 ..
+        assert_eq!(ring.read_frac(0.), 5.);
+        assert_eq!(ring.read_frac(1.), 4.);
+        assert_eq!(ring.read_frac(1.5), 3.5);
+        assert_eq!(ring.read_frac(3.25), 1.75);
+        // clamped to the oldest element
+        assert_eq!(ring.read_frac(42.), 0.);
+    }
 }
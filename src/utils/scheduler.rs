@@ -0,0 +1,76 @@
+/// events waiting for their frame (audio clock), for the RT thread.
+///
+/// events due at the same frame come out in the order they were pushed.
+pub struct Scheduler<T> {
+    // sorted by decreasing frame : the next event is the last one
+    events: Vec<(u64, T)>,
+}
+
+// events waiting at most, so that the RT thread never allocates
+const EVENTS: usize = 256;
+
+impl<T> Scheduler<T> {
+    pub fn new() -> Scheduler<T> {
+        Scheduler { events: Vec::with_capacity(EVENTS) }
+    }
+
+    /// the event comes back when the scheduler is full
+    pub fn push(&mut self, frame: u64, event: T) -> Result<(), T> {
+        if self.events.len() == EVENTS {
+            return Err(event);
+        }
+        let idx = self.events
+            .iter()
+            .position(|&(f, _)| f <= frame)
+            .unwrap_or(self.events.len());
+        self.events.insert(idx, (frame, event));
+        Ok(())
+    }
+
+    /// next event due at `frame` (late events are due right away)
+    pub fn pop(&mut self, frame: u64) -> Option<T> {
+        let due = match self.events.last() {
+            Some(&(f, _)) => f <= frame,
+            None => false,
+        };
+        if due {
+            self.events.pop().map(|(_, event)| event)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scheduler() {
+        let mut s = Scheduler::new();
+        s.push(10, "c").unwrap();
+        s.push(2, "a").unwrap();
+        s.push(10, "d").unwrap();
+        s.push(5, "b").unwrap();
+
+        assert_eq!(s.pop(1), None);
+        assert_eq!(s.pop(7), Some("a"));
+        assert_eq!(s.pop(7), Some("b"));
+        assert_eq!(s.pop(7), None);
+        // same frame : push order
+        assert_eq!(s.pop(10), Some("c"));
+        assert_eq!(s.pop(10), Some("d"));
+        assert_eq!(s.pop(100), None);
+    }
+
+    #[test]
+    fn test_scheduler_full() {
+        let mut s = Scheduler::new();
+        for frame in 0..EVENTS as u64 {
+            s.push(frame, frame).unwrap();
+        }
+        assert_eq!(s.push(0, 1000), Err(1000));
+        assert_eq!(s.pop(0), Some(0));
+        assert!(s.push(0, 1000).is_ok());
+    }
+}